@@ -0,0 +1,46 @@
+use std::env;
+use std::path::PathBuf;
+
+// The JS glue of the built-in extensions, evaluated once at build time into a
+// V8 startup snapshot. Their ops are registered by the real extensions when
+// `JsRunner::new` creates a runtime from it, so none of these scripts may
+// call an op while they are loaded.
+deno_core::extension!(my_extension, js = ["src/runtime.js"]);
+deno_core::extension!(datacache_extension, js = ["src/extensions/datacache.js"]);
+deno_core::extension!(database_extension, js = ["src/extensions/database.js"]);
+deno_core::extension!(websocket_extension, js = ["src/extensions/websocket.js"]);
+deno_core::extension!(timers_extension, js = ["src/extensions/timers.js"]);
+deno_core::extension!(fetch_extension, js = ["src/extensions/fetch.js"]);
+deno_core::extension!(metrics_extension, js = ["src/extensions/metrics.js"]);
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let extensions = vec![
+        my_extension::init_ops_and_esm(),
+        datacache_extension::init_ops_and_esm(),
+        database_extension::init_ops_and_esm(),
+        websocket_extension::init_ops_and_esm(),
+        timers_extension::init_ops_and_esm(),
+        fetch_extension::init_ops_and_esm(),
+        metrics_extension::init_ops_and_esm(),
+    ];
+    // checked against the runtime's extensions in `JsRunner::new`
+    let names = extensions.iter().map(|ext| ext.name).collect::<Vec<_>>();
+    std::fs::write(out_dir.join("SNAPSHOT_EXTENSIONS.txt"), names.join("\n")).unwrap();
+    let snapshot = deno_core::snapshot::create_snapshot(
+        deno_core::snapshot::CreateSnapshotOptions {
+            cargo_manifest_dir: env!("CARGO_MANIFEST_DIR"),
+            startup_snapshot: None,
+            skip_op_registration: true,
+            extensions,
+            extension_transpiler: None,
+            with_runtime_cb: None,
+        },
+        None,
+    )
+    .unwrap();
+    std::fs::write(out_dir.join("RUNTIME_SNAPSHOT.bin"), snapshot.output).unwrap();
+    for path in snapshot.files_loaded_during_snapshot {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+}
@@ -0,0 +1,23 @@
+use axum::routing::get;
+use axum::Router;
+use axum_script::ScriptApp;
+
+// Serves the routes of tests/setup.js under /scripts next to a native route.
+#[tokio::main]
+async fn main() {
+    let scripts = ScriptApp::builder()
+        .entry("tests/setup.js")
+        .workers(2)
+        .build()
+        .await
+        .unwrap();
+    let app = Router::new()
+        .route("/", get(|| async { "native axum route" }))
+        .nest("/scripts", scripts);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:4001")
+        .await
+        .unwrap();
+    println!("Server listening on {}", listener.local_addr().unwrap());
+    axum::serve(listener, app).await.unwrap();
+}
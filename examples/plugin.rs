@@ -0,0 +1,50 @@
+use axum_script::deno_core::{self, op2, Extension, OpState};
+use axum_script::{Plugin, ScriptApp};
+
+struct Salutation(String);
+
+#[op2]
+#[string]
+fn op_greeting(state: &mut OpState, #[string] name: String) -> String {
+    let salutation = state.borrow::<Salutation>();
+    return format!("{}, {}!", salutation.0, name);
+}
+
+deno_core::extension!(
+    greeting_extension,
+    ops = [op_greeting],
+    js = ["examples/plugin/greeting.js"]
+);
+
+struct Greeting {
+    salutation: String,
+}
+
+impl Plugin for Greeting {
+    fn extension(&self) -> Extension {
+        return greeting_extension::init_ops_and_esm();
+    }
+
+    fn init_state(&self, state: &mut OpState) {
+        state.put(Salutation(self.salutation.clone()));
+    }
+}
+
+// curl localhost:4001/hello/world
+#[tokio::main]
+async fn main() {
+    let app = ScriptApp::builder()
+        .entry("examples/plugin/setup.js")
+        .plugin(Greeting {
+            salutation: String::from("Hello"),
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:4001")
+        .await
+        .unwrap();
+    println!("Server listening on {}", listener.local_addr().unwrap());
+    axum::serve(listener, app).await.unwrap();
+}
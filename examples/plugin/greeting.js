@@ -0,0 +1,3 @@
+((globalThis) => {
+  globalThis.greeting = (name) => Deno.core.ops.op_greeting(name);
+})(globalThis);
@@ -0,0 +1,3 @@
+route("/hello/:name", (req) => {
+  return { json: { greeting: greeting(req.params.name) } };
+});
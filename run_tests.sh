@@ -1,9 +1,9 @@
 #!/usr/bin/env bash
 set -e
 
-rm -rf sqlite.db*
+rm -rf sqlite.db* datacache.json traces.jsonl
 
-cargo run tests/ &
+OTEL_TRACES_FILE=traces.jsonl cargo run tests/ &
 RSPID=$!
 trap "kill $RSPID" EXIT
 
@@ -11,4 +11,4 @@ while ! nc -z localhost 4000; do
   sleep 0.2 
 done
 
-deno test --allow-net tests/test.js
\ No newline at end of file
+deno test --allow-net --allow-read tests/test.js
\ No newline at end of file
@@ -0,0 +1,453 @@
+use crate::body::read_body;
+use crate::config::AppConfig;
+use crate::extensions::datacache::DataCache;
+use crate::extensions::websocket::ws_handler;
+use crate::logging::{request_id, with_access_log};
+use crate::metrics::{metrics_response, track_requests};
+use crate::openapi::openapi_document;
+use crate::plugin::Plugin;
+use crate::routing::{
+    check_route_conflicts, headers_to_json, query_to_json, route_key, split_route_key,
+    RouteOptions, RouteRequest, RouteState, WorkerPool,
+};
+use crate::runner::JsRunner;
+use crate::static_files::{mount_static_dir, static_dir_paths, StaticDir};
+use crate::validation::RequestValidator;
+use axum::body::Body;
+use axum::extract::{MatchedPath, RawPathParams};
+use axum::http::header::{ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderValue, Method};
+use axum::response::{IntoResponse, Response};
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware,
+    response::Html,
+    routing::{get, on, MethodFilter, MethodRouter},
+    Json, Router,
+};
+use deno_core::anyhow::{anyhow, bail, Context};
+use deno_core::error::AnyError;
+use deno_core::Extension;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration};
+use tracing::Instrument;
+
+// What the setup file registered, collected by a throwaway runtime before
+// the workers start.
+struct Discovery {
+    routes: HashMap<String, RouteOptions>,
+    static_dirs: Vec<StaticDir>,
+    config: AppConfig,
+    rebuild_cache: bool,
+}
+
+pub struct ScriptApp;
+
+impl ScriptApp {
+    pub fn builder() -> ScriptAppBuilder {
+        return ScriptAppBuilder {
+            entry: PathBuf::from("setup.js"),
+            workers: 1,
+            plugins: vec![],
+            transpile_cache: None,
+        };
+    }
+}
+
+pub struct ScriptAppBuilder {
+    entry: PathBuf,
+    workers: usize,
+    plugins: Vec<Arc<dyn Plugin>>,
+    transpile_cache: Option<PathBuf>,
+}
+
+impl ScriptAppBuilder {
+    // the setup file; a .js, .ts, .tsx or .jsx module
+    pub fn entry(mut self, entry: impl Into<PathBuf>) -> Self {
+        self.entry = entry.into();
+        return self;
+    }
+
+    // JS worker threads, each with its own runtime and copy of the app
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        return self;
+    }
+
+    // added to every JS runtime after the built-in extensions, in order
+    pub fn plugin(mut self, plugin: impl Plugin) -> Self {
+        self.plugins.push(Arc::new(plugin));
+        return self;
+    }
+
+    // where transpiled .ts, .tsx and .jsx modules are kept between starts,
+    // `$XDG_CACHE_HOME/axum_script/transpile` by default
+    pub fn transpile_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.transpile_cache = Some(dir.into());
+        return self;
+    }
+
+    // called once per runtime, e.g. `.extension(my_extension::init_ops_and_esm)`
+    pub fn extension(self, extension: impl Fn() -> Extension + Send + Sync + 'static) -> Self {
+        return self.plugin(extension);
+    }
+
+    // The runtimes are not Send, so the discovery runtime gets a thread of
+    // its own and can be used from any tokio runtime.
+    async fn discover(
+        &self,
+        cache: Arc<DataCache>,
+        populate_cache: bool,
+    ) -> Result<Discovery, AnyError> {
+        let entry = self.entry.clone();
+        let plugins = self.plugins.clone();
+        let transpile_cache = self.transpile_cache.clone();
+        let (tx, rx) = oneshot::channel();
+        let setup_thread = thread::Builder::new().name(String::from("js-setup"));
+        setup_thread.spawn(move || {
+            let discovery = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async {
+                    let transpile_cache = transpile_cache.as_deref();
+                    let runner = JsRunner::new(&entry, None, &plugins, transpile_cache, cache)
+                        .await
+                        .with_context(|| format!("Failed to load {}", entry.display()))?;
+                    let routes = runner
+                        .routes
+                        .iter()
+                        .map(|(path, entry)| (path.clone(), entry.options.clone()))
+                        .collect::<HashMap<_, _>>();
+                    let rebuild_cache = populate_cache && runner.populate_initial_cache().await;
+                    Ok::<_, AnyError>(Discovery {
+                        routes,
+                        static_dirs: runner.static_dirs.clone(),
+                        config: runner.config.clone(),
+                        rebuild_cache,
+                    })
+                });
+            let _ = tx.send(discovery);
+        })?;
+        return rx
+            .await
+            .map_err(|_| anyhow!("loading {} panicked", self.entry.display()))?;
+    }
+
+    // the OpenAPI document of the registered routes, without serving them
+    pub async fn openapi(self) -> Result<Value, AnyError> {
+        let discovery = self.discover(DataCache::new(), false).await?;
+        return Ok(openapi_document(
+            &discovery.routes,
+            &discovery.config.openapi,
+        ));
+    }
+
+    // Starts the JS workers and returns a router that can be served on its
+    // own or nested into another axum app. Every app has its own data cache;
+    // the Prometheus registry is process-wide, so apps built in the same
+    // process share their metrics.
+    pub async fn build(self) -> Result<Router, AnyError> {
+        let cache = DataCache::new();
+        let Discovery {
+            routes: paths,
+            static_dirs,
+            config,
+            rebuild_cache,
+        } = self.discover(Arc::clone(&cache), true).await?;
+
+        let stats_path = cache.stats_path();
+        let openapi_path = config.openapi.path.clone();
+        let metrics_path = config.metrics.path.clone();
+        if paths.contains_key("__not_found") && static_dirs.iter().any(|dir| dir.prefix == "/") {
+            bail!("staticDir(\"/\") and notFound() both handle unmatched paths, use only one");
+        }
+        let static_paths = static_dirs.iter().flat_map(static_dir_paths);
+        let builtin_paths = stats_path
+            .iter()
+            .chain(openapi_path.iter())
+            .chain(metrics_path.iter())
+            .cloned()
+            .chain(static_paths)
+            .collect::<Vec<_>>();
+        // the same path may be registered once per method
+        let route_paths = paths
+            .keys()
+            .map(|key| split_route_key(key).map_or(key.as_str(), |(_, path)| path))
+            .collect::<BTreeSet<_>>();
+        let all_paths = route_paths
+            .into_iter()
+            .chain(builtin_paths.iter().map(|path| path.as_str()));
+        check_route_conflicts(all_paths)?;
+
+        let mut validators = HashMap::new();
+        for (key, options) in &paths {
+            if let Some(schema) = &options.schema {
+                validators.insert(key.clone(), RequestValidator::compile(key, schema)?);
+            }
+        }
+
+        let mut method_routers: BTreeMap<&str, MethodRouter<RouteState>> = BTreeMap::new();
+        for (key, options) in &paths {
+            let Some((method, path)) = split_route_key(key) else {
+                continue;
+            };
+            let filter = Method::from_bytes(method.as_bytes())
+                .ok()
+                .and_then(|method| MethodFilter::try_from(method).ok());
+            let Some(filter) = filter else {
+                bail!("route {} uses an unsupported method", key);
+            };
+            let method_router = match method_routers.remove(path) {
+                Some(method_router) if options.websocket => method_router.on(filter, ws_handler),
+                Some(method_router) => method_router.on(filter, req_handler),
+                None if options.websocket => on(filter, ws_handler),
+                None => on(filter, req_handler),
+            };
+            method_routers.insert(path, method_router);
+        }
+        let app = method_routers
+            .into_iter()
+            .fold(Router::new(), |router, (path, method_router)| {
+                router.route(path, method_router)
+            });
+        let app = if paths.contains_key("__not_found") {
+            app.fallback(not_found_handler)
+        } else {
+            app
+        };
+
+        let mut senders = vec![];
+        let mut starting = vec![];
+        for i in 0..self.workers {
+            let (sender, ready) = JsRunner::spawn_thread(
+                i,
+                self.entry.clone(),
+                self.plugins.clone(),
+                self.transpile_cache.clone(),
+                Arc::clone(&cache),
+            )?;
+            senders.push(sender);
+            starting.push(ready);
+        }
+        // the workers load the app in parallel; none may be left without one
+        for (i, ready) in starting.into_iter().enumerate() {
+            ready
+                .await
+                .map_err(|_| anyhow!("JS worker {} stopped while starting", i))?
+                .with_context(|| {
+                    format!("JS worker {} failed to load {}", i, self.entry.display())
+                })?;
+        }
+        if rebuild_cache {
+            // serve the persisted snapshot while a worker rebuilds it
+            senders[0]
+                .send(RouteRequest {
+                    route_name: String::from("__create_cache"),
+                    ..Default::default()
+                })
+                .await
+                .map_err(|_| anyhow!("the JS worker stopped"))?;
+        }
+
+        let openapi = openapi_path.map(|path| (path, openapi_document(&paths, &config.openapi)));
+        let workers = Arc::new(WorkerPool::new(senders));
+        let rstate = RouteState {
+            workers: Arc::clone(&workers),
+            routes: Arc::new(paths),
+            config: Arc::new(config),
+            validators: Arc::new(validators),
+            cache: Arc::clone(&cache),
+        };
+        let app: Router = app.with_state(rstate);
+        let app = match stats_path {
+            Some(stats_path) => {
+                let stats = move || {
+                    let cache = Arc::clone(&cache);
+                    async move { Json(cache.stats()) }
+                };
+                app.route(&stats_path, get(stats))
+            }
+            None => app,
+        };
+        let app = match openapi {
+            Some((path, doc)) => app.route(
+                &path,
+                get(move || {
+                    let doc = doc.clone();
+                    async move { Json(doc) }
+                }),
+            ),
+            None => app,
+        };
+        let app = static_dirs.iter().fold(app, mount_static_dir);
+        let app = match metrics_path {
+            // a layer rather than a route layer, so the fallback is counted too
+            Some(path) => app.layer(middleware::from_fn(track_requests)).route(
+                &path,
+                get(move || async move { metrics_response(&workers) }),
+            ),
+            None => app,
+        };
+        return Ok(with_access_log(app));
+    }
+}
+
+async fn req_handler(
+    State(state): State<RouteState>,
+    match_path: MatchedPath,
+    raw_params: RawPathParams,
+    req: Request,
+) -> Response<Body> {
+    // HEAD requests are answered by the GET handler
+    let method = if req.method() == Method::HEAD {
+        Method::GET
+    } else {
+        req.method().clone()
+    };
+    let key = route_key(method.as_str(), match_path.as_str());
+    let options = state.routes.get(&key).cloned().unwrap_or_default();
+    let etag = if options.cache_etag {
+        let etag = state.cache.etag();
+        if etag_matches(req.headers().get(IF_NONE_MATCH), &etag) {
+            return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+        }
+        Some(etag)
+    } else {
+        None
+    };
+    let mut parvals =
+        serde_json::Map::from_iter(raw_params.iter().map(|(k, v)| (String::from(k), v.into())));
+    let mut parts = request_parts(&req);
+    let (head, body) = req.into_parts();
+    let limit = options.body_limit.unwrap_or(state.config.body_limit);
+    let body = match read_body(&head.headers, body, limit).await {
+        Ok(body) => body,
+        Err(e) => return e.into_response(),
+    };
+    if let Some(validator) = state.validators.get(&key) {
+        let checked = validator.check(&mut parvals, &mut parts.query, body.value.as_ref());
+        if let Err(resp) = checked {
+            return resp;
+        }
+    }
+    let timeout_ms = options.timeout.or(state.config.timeout);
+    let mut resp = dispatch(
+        &state,
+        RouteRequest {
+            route_name: key,
+            route_args: parvals,
+            body: body.value,
+            ..parts
+        },
+        timeout_ms,
+    )
+    .await;
+    if let Some(etag) = etag {
+        resp.headers_mut()
+            .insert(ETAG, HeaderValue::from_str(&etag).unwrap());
+    }
+    return body.uploads.keep_until_sent(resp);
+}
+
+async fn not_found_handler(State(state): State<RouteState>, req: Request) -> Response<Body> {
+    return dispatch(
+        &state,
+        RouteRequest {
+            route_name: String::from("__not_found"),
+            ..request_parts(&req)
+        },
+        state.config.timeout,
+    )
+    .await;
+}
+
+fn request_parts(req: &Request) -> RouteRequest {
+    return RouteRequest {
+        method: req.method().to_string(),
+        path: String::from(req.uri().path()),
+        query: query_to_json(req.uri().query()),
+        headers: headers_to_json(req.headers()),
+        request_id: request_id(req.headers()),
+        ..Default::default()
+    };
+}
+
+// The span covers the hop to the JS worker and the wait for its answer; the
+// worker's handler span is its child.
+async fn dispatch(
+    state: &RouteState,
+    route_req: RouteRequest,
+    timeout_ms: Option<u64>,
+) -> Response<Body> {
+    let span = tracing::info_span!("dispatch", route = %route_req.route_name);
+    let route_req = RouteRequest {
+        span: Some(span.clone()),
+        ..route_req
+    };
+    return send_to_worker(state, route_req, timeout_ms)
+        .instrument(span)
+        .await;
+}
+
+// Dropping this future, e.g. when the client disconnects, drops `abort_tx`
+// and so aborts the signal of the JS request.
+async fn send_to_worker(
+    state: &RouteState,
+    route_req: RouteRequest,
+    timeout_ms: Option<u64>,
+) -> Response<Body> {
+    let (tx, rx) = oneshot::channel();
+    let (abort_tx, abort_rx) = oneshot::channel();
+    let sendres = state
+        .workers
+        .sender()
+        .send(RouteRequest {
+            response_channel: Some(tx),
+            abort: Some(abort_rx),
+            ..route_req
+        })
+        .await;
+    match sendres {
+        Ok(_) => {
+            let res = match timeout_ms {
+                Some(ms) => timeout(Duration::from_millis(ms), rx).await,
+                None => Ok(rx.await),
+            };
+            match res {
+                Ok(Ok(v)) => {
+                    let _ = abort_tx.send(false);
+                    v
+                }
+                Ok(Err(e)) => {
+                    tracing::error!("the JS worker dropped the request: {}", e);
+                    return (StatusCode::INTERNAL_SERVER_ERROR, Html("Error")).into_response();
+                }
+                Err(_) => {
+                    let _ = abort_tx.send(true);
+                    return (StatusCode::GATEWAY_TIMEOUT, Html("Gateway Timeout")).into_response();
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!("the JS worker stopped: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Html("Error")).into_response();
+        }
+    }
+}
+
+fn etag_matches(if_none_match: Option<&HeaderValue>, etag: &str) -> bool {
+    match if_none_match.and_then(|v| v.to_str().ok()) {
+        Some(v) => v.split(',').any(|candidate| {
+            let candidate = candidate.trim();
+            candidate == "*" || candidate.trim_start_matches("W/") == etag
+        }),
+        None => false,
+    }
+}
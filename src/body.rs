@@ -0,0 +1,212 @@
+use crate::routing::query_to_json;
+use axum::body::Body;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Handle;
+
+const UPLOAD_PREFIX: &str = "axum_script-upload-";
+
+static NEXT_UPLOAD: AtomicU64 = AtomicU64::new(0);
+
+// Chunks queued between JS and the peer of a streamed body. Kept small so a
+// slow peer makes the JS writes wait instead of buffering the whole body.
+pub const STREAM_CHUNKS: usize = 8;
+
+// What the JS request helpers are built from: `value` holds the raw `text`,
+// the urlencoded or multipart `form` fields and the multipart `parts`. File
+// parts are written to temp files owned by `uploads`.
+#[derive(Default)]
+pub struct RequestBody {
+    pub value: Option<Value>,
+    pub uploads: UploadGuard,
+}
+
+// Owns the temp files of a request's uploads and deletes them when dropped,
+// whether the request completed, failed validation or the client went away.
+#[derive(Default)]
+pub struct UploadGuard(Vec<PathBuf>);
+
+impl UploadGuard {
+    // Keeps the files until `resp` has been sent (or dropped), since a
+    // streaming response may still be reading them after the handler returned.
+    pub fn keep_until_sent(self, resp: Response) -> Response {
+        if self.0.is_empty() {
+            return resp;
+        }
+        return resp.map(|body| {
+            Body::from_stream(body.into_data_stream().map(move |chunk| {
+                let _ = &self;
+                chunk
+            }))
+        });
+    }
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        let uploads = std::mem::take(&mut self.0);
+        if uploads.is_empty() {
+            return;
+        }
+        let remove = move || {
+            for path in uploads {
+                let _ = std::fs::remove_file(path);
+            }
+        };
+        match Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(remove);
+            }
+            Err(_) => remove(),
+        }
+    }
+}
+
+pub enum BodyError {
+    TooLarge,
+    Invalid(String),
+}
+
+impl IntoResponse for BodyError {
+    fn into_response(self) -> Response {
+        match self {
+            BodyError::TooLarge => {
+                (StatusCode::PAYLOAD_TOO_LARGE, Html("Payload Too Large")).into_response()
+            }
+            BodyError::Invalid(e) => {
+                (StatusCode::BAD_REQUEST, Html(format!("Bad Request: {}", e))).into_response()
+            }
+        }
+    }
+}
+
+fn mime_essence(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let essence = content_type.split(';').next().unwrap_or("");
+    return Some(essence.trim().to_ascii_lowercase());
+}
+
+pub async fn read_body(
+    headers: &HeaderMap,
+    body: Body,
+    limit: usize,
+) -> Result<RequestBody, BodyError> {
+    let content_length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if content_length.is_some_and(|len| len > limit) {
+        return Err(BodyError::TooLarge);
+    }
+    let essence = mime_essence(headers);
+    if essence.as_deref() == Some("multipart/form-data") {
+        let content_type = headers.get(CONTENT_TYPE).unwrap().to_str().unwrap_or("");
+        let boundary =
+            multer::parse_boundary(content_type).map_err(|e| BodyError::Invalid(e.to_string()))?;
+        // dropping the guard removes the files written so far on errors
+        let mut uploads = UploadGuard::default();
+        let value = read_multipart(body, boundary, limit, &mut uploads.0).await?;
+        return Ok(RequestBody {
+            value: Some(value),
+            uploads,
+        });
+    }
+
+    let bytes = axum::body::to_bytes(body, limit)
+        .await
+        .map_err(|_| BodyError::TooLarge)?;
+    if bytes.is_empty() {
+        return Ok(RequestBody::default());
+    }
+    let text = String::from_utf8_lossy(&bytes).into_owned();
+    let form = if essence.as_deref() == Some("application/x-www-form-urlencoded") {
+        Some(query_to_json(Some(&text)))
+    } else {
+        None
+    };
+    return Ok(RequestBody {
+        value: Some(json!({ "text": text, "form": form })),
+        uploads: UploadGuard::default(),
+    });
+}
+
+// Text fields are kept in memory, file parts are streamed to temp files so
+// that large uploads never sit in memory as a whole.
+async fn read_multipart(
+    body: Body,
+    boundary: String,
+    limit: usize,
+    uploads: &mut Vec<PathBuf>,
+) -> Result<Value, BodyError> {
+    let constraints =
+        multer::Constraints::new().size_limit(multer::SizeLimit::new().whole_stream(limit as u64));
+    let mut multipart =
+        multer::Multipart::with_constraints(body.into_data_stream(), boundary, constraints);
+    let mut form = serde_json::Map::new();
+    let mut parts = vec![];
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        let name = field.name().map(String::from);
+        let content_type = field.content_type().map(|mime| mime.to_string());
+        match field.file_name().map(String::from) {
+            Some(filename) => {
+                let path = upload_path();
+                uploads.push(path.clone());
+                let mut file = tokio::fs::File::create(&path)
+                    .await
+                    .map_err(|e| BodyError::Invalid(e.to_string()))?;
+                let mut size = 0;
+                while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+                    size += chunk.len();
+                    file.write_all(&chunk)
+                        .await
+                        .map_err(|e| BodyError::Invalid(e.to_string()))?;
+                }
+                parts.push(json!({
+                    "name": name,
+                    "filename": filename,
+                    "contentType": content_type,
+                    "path": path,
+                    "size": size,
+                }));
+            }
+            None => {
+                let value = field.text().await.map_err(multipart_error)?;
+                if let Some(name) = &name {
+                    form.insert(name.clone(), Value::String(value.clone()));
+                }
+                parts.push(json!({
+                    "name": name,
+                    "contentType": content_type,
+                    "value": value,
+                }));
+            }
+        }
+    }
+    return Ok(json!({ "text": "", "form": form, "parts": parts }));
+}
+
+fn multipart_error(e: multer::Error) -> BodyError {
+    match e {
+        multer::Error::StreamSizeExceeded { .. } => BodyError::TooLarge,
+        e => BodyError::Invalid(e.to_string()),
+    }
+}
+
+fn upload_path() -> PathBuf {
+    let id = NEXT_UPLOAD.fetch_add(1, Ordering::Relaxed);
+    return env::temp_dir().join(format!("{}{}-{}", UPLOAD_PREFIX, std::process::id(), id));
+}
+
+// JS may only read back the temp files created for uploads
+pub fn is_upload_path(path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str());
+    return path.parent() == Some(env::temp_dir().as_path())
+        && name.is_some_and(|name| name.starts_with(UPLOAD_PREFIX));
+}
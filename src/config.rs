@@ -0,0 +1,85 @@
+use crate::extensions::fetch::FetchConfig;
+use crate::metrics::MetricsConfig;
+use deno_core::anyhow::bail;
+use deno_core::error::AnyError;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+// Next to the entry file and read before it is loaded: the settings of
+// `configure({...})`, which the setup file can still override, and an
+// import map in `imports` and `scopes` added to `imports.json`.
+pub const CONFIG_FILE: &str = "axum_script.json";
+
+// settings passed to `configure({...})` in the setup file
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AppConfig {
+    // maximum request body size in bytes, per route with `bodyLimit`
+    pub body_limit: usize,
+    // check `json` responses against the route's response schema, meant
+    // for development as it costs time on every request
+    pub validate_responses: bool,
+    pub openapi: OpenApiConfig,
+    // milliseconds before a handler is answered with 504
+    pub timeout: Option<u64>,
+    pub fetch: FetchConfig,
+    pub metrics: MetricsConfig,
+}
+
+// `openapi: { path: "/openapi.json", title, version }`, served only with a path
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OpenApiConfig {
+    pub path: Option<String>,
+    pub title: String,
+    pub version: String,
+}
+
+impl Default for OpenApiConfig {
+    fn default() -> Self {
+        OpenApiConfig {
+            path: None,
+            title: String::from("axum_script"),
+            version: String::from(env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            body_limit: 2 * 1024 * 1024,
+            validate_responses: false,
+            openapi: OpenApiConfig::default(),
+            timeout: None,
+            fetch: FetchConfig::default(),
+            metrics: MetricsConfig::default(),
+        }
+    }
+}
+
+impl AppConfig {
+    // later `configure()` calls only override the keys they pass
+    pub fn merge(&self, values: serde_json::Map<String, Value>) -> Result<AppConfig, AnyError> {
+        let mut merged = serde_json::to_value(self)?;
+        merged.as_object_mut().unwrap().extend(values);
+        return Ok(serde_json::from_value(merged)?);
+    }
+}
+
+// the config file's object, empty when the app has none
+pub fn read_config_file(entry_file: &Path) -> Result<Map<String, Value>, AnyError> {
+    let path = entry_file.with_file_name(CONFIG_FILE);
+    let json = match fs::read_to_string(&path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Map::new()),
+        Err(e) => bail!("cannot read {}: {}", path.display(), e),
+    };
+    match serde_json::from_str::<Value>(&json) {
+        Ok(Value::Object(config)) => return Ok(config),
+        Ok(_) => bail!("{} must hold a JSON object", path.display()),
+        Err(e) => bail!("invalid config file {}: {}", path.display(), e),
+    }
+}
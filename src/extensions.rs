@@ -1,2 +1,6 @@
 pub mod database;
 pub mod datacache;
+pub mod fetch;
+pub mod metrics;
+pub mod timers;
+pub mod websocket;
@@ -1,7 +1,8 @@
 ((globalThis) => {
   const core = Deno.core;
 
-  globalThis.query = (sql, pars = []) => Deno.core.ops.op_query(sql, pars);
-  globalThis.execute = (sql, pars = []) => Deno.core.ops.op_execute(sql, pars);
-  globalThis.connectToDatabase = Deno.core.ops.op_connect_db;
+  // `req.db` has the same two with the request's spans as parent
+  globalThis.query = (sql, pars = []) => Deno.core.ops.op_query(sql, pars, 0);
+  globalThis.execute = (sql, pars = []) => Deno.core.ops.op_execute(sql, pars, 0);
+  globalThis.connectToDatabase = (url) => Deno.core.ops.op_connect_db(url);
 })(globalThis);
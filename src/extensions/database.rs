@@ -1,3 +1,5 @@
+use crate::logging::request_span;
+use crate::metrics::{metrics, record_db_connections, worker_label, DbPoolMetrics};
 use crate::sqltojson::row_to_json;
 use deno_core::op2;
 use deno_core::OpState;
@@ -8,16 +10,38 @@ use sqlx::{migrate::MigrateDatabase, Any, AnyPool, Sqlite};
 use std::cell::RefCell;
 use std::env;
 use std::rc::Rc;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+fn record_query(op: &str, pool: &Pool<Any>, started: Instant) {
+    metrics()
+        .db_query_duration
+        .with_label_values(&[op])
+        .observe(started.elapsed().as_secs_f64());
+    record_db_connections(&worker_label(), pool);
+}
+
+// a child of the request JS passed the `context` of
+fn db_span(state: &OpState, context: u32, op: &str, sql: &str) -> tracing::Span {
+    return tracing::info_span!(
+        parent: request_span(state, context).id(),
+        "db.query",
+        otel.kind = "client",
+        db.operation = op,
+        db.statement = sql
+    );
+}
 
 //async fn op_connect_db(state: Rc<RefCell<OpState>>, #[serde] conn_obj: serde_json::Value) -> () {
 
 #[op2(async)]
 async fn op_connect_db(state: Rc<RefCell<OpState>>, #[string] conn_obj: String) -> () {
-    let state = state.borrow();
-
-    let opoolref = state.borrow::<Rc<RefCell<Option<Pool<Any>>>>>();
-
     let pool = connect_database(&conn_obj).await;
+    let mut state = state.borrow_mut();
+    // forget an earlier connection's pool before this one is registered
+    drop(state.try_take::<DbPoolMetrics>());
+    state.put(DbPoolMetrics::register(&pool));
+    let opoolref = state.borrow::<Rc<RefCell<Option<Pool<Any>>>>>();
     opoolref.replace(Some(pool));
     return ();
 }
@@ -28,11 +52,16 @@ async fn op_query(
     state: Rc<RefCell<OpState>>,
     #[string] sqlq: String,
     #[serde] pars: Vec<serde_json::Value>,
+    context: u32,
 ) -> serde_json::Value {
-    let state = state.borrow();
-    let opoolref = state.borrow::<Rc<RefCell<Option<Pool<Any>>>>>();
-    let opool = opoolref.borrow();
-    if let Some(pool) = &(*opool) {
+    // not borrowed across the await, other ops run meanwhile
+    let (opool, span) = {
+        let state = state.borrow();
+        let opoolref = state.borrow::<Rc<RefCell<Option<Pool<Any>>>>>();
+        let opool = opoolref.borrow().clone();
+        (opool, db_span(&state, context, "query", &sqlq))
+    };
+    if let Some(pool) = opool {
         //let mut q =;
 
         let boundq: sqlx::query::Query<Any, sqlx::any::AnyArguments> =
@@ -49,7 +78,9 @@ async fn op_query(
                     }
                     _ => panic!("unknonw argumen"),
                 });
-        let rows = boundq.fetch_all(&(*pool)).await.unwrap();
+        let started = Instant::now();
+        let rows = boundq.fetch_all(&pool).instrument(span).await.unwrap();
+        record_query("query", &pool, started);
         let rows: Vec<Value> = rows.iter().map(row_to_json).collect();
         return Value::Array(rows);
     } else {
@@ -63,11 +94,16 @@ async fn op_execute(
     state: Rc<RefCell<OpState>>,
     #[string] sqlq: String,
     #[serde] pars: Vec<serde_json::Value>,
+    context: u32,
 ) -> () {
-    let state = state.borrow();
-    let opoolref = state.borrow::<Rc<RefCell<Option<Pool<Any>>>>>();
-    let opool = opoolref.borrow();
-    if let Some(pool) = &(*opool) {
+    // not borrowed across the await, other ops run meanwhile
+    let (opool, span) = {
+        let state = state.borrow();
+        let opoolref = state.borrow::<Rc<RefCell<Option<Pool<Any>>>>>();
+        let opool = opoolref.borrow().clone();
+        (opool, db_span(&state, context, "execute", &sqlq))
+    };
+    if let Some(pool) = opool {
         let boundq: sqlx::query::Query<Any, sqlx::any::AnyArguments> =
             pars.into_iter()
                 .fold(sqlx::query(&sqlq), |q, par| match par {
@@ -83,11 +119,13 @@ async fn op_execute(
                     }
                     _ => panic!("unknonw argumen"),
                 });
-        let qres = boundq.execute(&(*pool)).await;
+        let started = Instant::now();
+        let qres = boundq.execute(&pool).instrument(span).await;
+        record_query("execute", &pool, started);
         match qres {
             Ok(_v) => return (),
             Err(e) => {
-                dbg!(e);
+                tracing::error!("{}", e);
                 panic!("error in execute")
             }
         };
@@ -109,13 +147,13 @@ deno_core::extension!(
 pub async fn connect_database(db_url: &str) -> Pool<Any> {
     sqlx::any::install_default_drivers();
     if !Sqlite::database_exists(db_url).await.unwrap_or(false) {
-        println!("Creating database {}", db_url);
+        tracing::info!("Creating database {}", db_url);
         match Sqlite::create_database(db_url).await {
-            Ok(_) => println!("Create db success"),
+            Ok(_) => tracing::info!("Create db success"),
             Err(error) => panic!("error: {}", error),
         }
     } else {
-        println!("Database already exists");
+        tracing::info!("Database already exists");
     }
     let dbr = AnyPool::connect(db_url).await;
     match dbr {
@@ -1,8 +1,41 @@
 ((globalThis) => {
   const core = Deno.core;
 
-  globalThis.createCache = Deno.core.ops.op_create_cache;
-  globalThis.flushCache = Deno.core.ops.op_flush_cache;
+  globalThis.createCache = (create, options) =>
+    Deno.core.ops.op_create_cache(create, options);
+  globalThis.flushCache = () => Deno.core.ops.op_flush_cache();
+  globalThis.cacheVersion = () => Deno.core.ops.op_cache_version();
+  globalThis.cacheStats = () => Deno.core.ops.op_cache_stats();
+  globalThis.updateCache = (key, value) =>
+    Deno.core.ops.op_update_cache(key, value ?? null);
+  globalThis.patchCache = (patch) => Deno.core.ops.op_patch_cache(patch);
+
+  const cacheChangeCallbacks = [];
+
+  async function dispatchCacheChanges() {
+    while (true) {
+      const next = Deno.core.ops.op_next_cache_change();
+      // waiting for changes must not keep the setup phase alive
+      core.unrefOpPromise(next);
+      const change = await next;
+      if (!change) return;
+      for (const callback of cacheChangeCallbacks) {
+        try {
+          await callback(change);
+        } catch (e) {
+          console.error("onCacheChange callback failed", String(e));
+        }
+      }
+    }
+  }
+
+  globalThis.onCacheChange = (callback) => {
+    cacheChangeCallbacks.push(callback);
+    if (cacheChangeCallbacks.length === 1) {
+      Deno.core.ops.op_subscribe_cache_changes();
+      dispatchCacheChanges();
+    }
+  };
   globalThis.getCache = (subset) =>
     typeof subset === "function"
       ? Deno.core.ops.op_with_cache(subset)
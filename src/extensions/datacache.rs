@@ -1,30 +1,149 @@
-use crate::routing::RouteRequest;
+use crate::routing::{RouteEntry, RouteOptions, RouteRequest};
+use axum::body::Body;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use deno_core::anyhow::anyhow;
+use deno_core::error::AnyError;
 use deno_core::op2;
 use deno_core::serde_v8::from_v8;
 use deno_core::{serde_v8::to_v8, OpState};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::env;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::RwLock;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 
-static CACHE_VALUE_LOCK: RwLock<Value> = RwLock::new(Value::Null);
+// every published value is a new generation; `version` only ever increases
+struct CacheGeneration {
+    value: Value,
+    version: u64,
+    hash: String,
+    size_bytes: usize,
+}
+
+// The cache of one app, shared by its workers and the runtime that discovers
+// its routes. Every app built in the process has its own.
+pub struct DataCache {
+    value: RwLock<CacheGeneration>,
+    persist_path: RwLock<Option<PathBuf>>,
+    changes: broadcast::Sender<CacheChange>,
+    stats_path: RwLock<Option<String>>,
+    stats: Mutex<CacheStats>,
+}
+
+// one writer thread for the process, the jobs name the file they belong to
+static CACHE_PERSIST_WRITER: OnceLock<mpsc::UnboundedSender<PersistJob>> = OnceLock::new();
+
+// reads of the whole cache (no subset) are counted under this key
+const WHOLE_CACHE_KEY: &str = "*";
+
+// bump when the layout of the persisted snapshot changes
+const CACHE_FILE_VERSION: u32 = 1;
+
+#[derive(Deserialize, Default)]
+struct CacheOptions {
+    persist: Option<String>,
+    stats: Option<String>,
+}
+
+#[derive(Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+struct CacheStats {
+    builds: u64,
+    failures: u64,
+    last_build_ms: Option<f64>,
+    total_build_ms: f64,
+    last_built_at: Option<String>,
+    last_error: Option<String>,
+    key_reads: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedCache {
+    version: u32,
+    #[serde(default)]
+    generation: u64,
+    hash: String,
+    data: Value,
+}
+
+struct PersistJob {
+    path: PathBuf,
+    snapshot: PersistedCache,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CacheChange {
+    pub version: u64,
+    pub hash: String,
+    pub changed: Vec<String>,
+    // new values of the changed top-level keys, removed keys map to null
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<serde_json::Map<String, Value>>,
+}
+
+#[derive(Serialize)]
+struct CacheVersion {
+    version: u64,
+    hash: String,
+    etag: String,
+}
+
+fn data_cache(state: &OpState) -> Arc<DataCache> {
+    return Arc::clone(state.borrow::<Arc<DataCache>>());
+}
+
+#[op2()]
+#[serde]
+fn op_get_cache_value(state: &mut OpState) -> serde_json::Value {
+    let cache = data_cache(state);
+    cache.count_key_reads([WHOLE_CACHE_KEY]);
+    let r1 = cache.value.read().unwrap();
+    return r1.value.clone(); //TODO this is bad
+}
+
+#[op2()]
+#[serde]
+fn op_cache_stats(state: &mut OpState) -> serde_json::Value {
+    return data_cache(state).stats();
+}
 
 #[op2()]
 #[serde]
-fn op_get_cache_value() -> serde_json::Value {
-    let r1 = CACHE_VALUE_LOCK.read().unwrap();
-    return (*r1).clone(); //TODO this is bad
+fn op_cache_version(state: &mut OpState) -> CacheVersion {
+    let cache = data_cache(state);
+    let r1 = cache.value.read().unwrap();
+    return CacheVersion {
+        version: r1.version,
+        hash: r1.hash.clone(),
+        etag: generation_etag(&r1),
+    };
 }
 
 #[op2()]
 #[serde]
-fn op_get_cache_subset_value(#[serde] subset: serde_json::Value) -> serde_json::Value {
+fn op_get_cache_subset_value(
+    state: &mut OpState,
+    #[serde] subset: serde_json::Value,
+) -> serde_json::Value {
     //fn op_get_cache_subset_value(subset: serde_json::Value) -> Value {
-    let r1 = CACHE_VALUE_LOCK.read().unwrap();
-    match (subset, &(*r1)) {
+    let cache = data_cache(state);
+    match &subset {
+        Value::String(key) => cache.count_key_reads([key.as_str()]),
+        Value::Array(keys) => cache.count_key_reads(keys.iter().filter_map(|k| k.as_str())),
+        _ => (),
+    }
+    let r1 = cache.value.read().unwrap();
+    match (subset, &r1.value) {
         (Value::String(key), Value::Object(o)) => o.get(&key).unwrap_or(&Value::Null).clone(),
         (Value::Array(keys), Value::Object(o)) => {
             let mut mp = serde_json::Map::new();
@@ -44,10 +163,30 @@ fn op_get_cache_subset_value(#[serde] subset: serde_json::Value) -> serde_json::
 }
 
 #[op2()]
-fn op_create_cache(state: &mut OpState, #[global] create_cache_fn: v8::Global<v8::Function>) -> () {
-    let hmref = state.borrow::<Rc<RefCell<HashMap<String, v8::Global<v8::Function>>>>>();
+fn op_create_cache(
+    state: &mut OpState,
+    #[global] create_cache_fn: v8::Global<v8::Function>,
+    #[serde] options: Option<CacheOptions>,
+) -> () {
+    let options = options.unwrap_or_default();
+    let cache = data_cache(state);
+    if let Some(path) = options.persist {
+        let mut persist_path = cache.persist_path.write().unwrap();
+        *persist_path = Some(PathBuf::from(path));
+    }
+    if let Some(path) = options.stats {
+        let mut stats_path = cache.stats_path.write().unwrap();
+        *stats_path = Some(path);
+    }
+    let hmref = state.borrow::<Rc<RefCell<HashMap<String, RouteEntry>>>>();
     let mut routes = hmref.borrow_mut();
-    routes.insert(String::from("__create_cache"), create_cache_fn);
+    routes.insert(
+        String::from("__create_cache"),
+        RouteEntry {
+            handler: create_cache_fn,
+            options: RouteOptions::default(),
+        },
+    );
     return ();
     //    return rows.len().try_into().unwrap();
 }
@@ -56,11 +195,18 @@ fn op_create_cache(state: &mut OpState, #[global] create_cache_fn: v8::Global<v8
 #[serde]
 fn op_with_cache<'s>(
     scope: &mut v8::HandleScope<'s>,
+    state: Rc<RefCell<OpState>>,
     #[global] gxformer: v8::Global<v8::Function>,
 ) -> serde_json::Value {
-    let r1 = CACHE_VALUE_LOCK.read().unwrap();
+    // not borrowed while the callback runs, it may call other cache ops
+    let cache = data_cache(&state.borrow());
+    cache.count_key_reads([WHOLE_CACHE_KEY]);
+    // release the lock before calling into JS, the callback may update the cache
+    let v8_val = {
+        let r1 = cache.value.read().unwrap();
+        to_v8(scope, &r1.value).unwrap()
+    };
     let xformer = gxformer.open(scope);
-    let v8_val = to_v8(scope, &(*r1)).unwrap();
     let fres = xformer.call(scope, v8_val, &[v8_val]);
     match fres {
         Some(v) => {
@@ -73,19 +219,75 @@ fn op_with_cache<'s>(
     //    return rows.len().try_into().unwrap();
 }
 
+#[op2()]
+fn op_update_cache(
+    state: &mut OpState,
+    #[string] key: String,
+    #[serde] value: serde_json::Value,
+) -> Result<(), AnyError> {
+    data_cache(state).update(|cache| match cache {
+        Value::Object(o) => {
+            o.insert(key, value);
+            Ok(())
+        }
+        Value::Null => {
+            let mut o = serde_json::Map::new();
+            o.insert(key, value);
+            *cache = Value::Object(o);
+            Ok(())
+        }
+        _ => Err(anyhow!("updateCache requires the cache to be an object")),
+    })
+}
+
+#[op2()]
+fn op_patch_cache(state: &mut OpState, #[serde] patch: json_patch::Patch) -> Result<(), AnyError> {
+    data_cache(state).update(|cache| Ok(json_patch::patch(cache, &patch)?))
+}
+
+#[op2(fast)]
+fn op_subscribe_cache_changes(state: &mut OpState) {
+    let rxref = state.borrow::<Rc<RefCell<Option<broadcast::Receiver<CacheChange>>>>>();
+    if rxref.borrow().is_none() {
+        rxref.replace(Some(data_cache(state).changes.subscribe()));
+    }
+}
+
+#[op2(async)]
+#[serde]
+async fn op_next_cache_change(state: Rc<RefCell<OpState>>) -> Option<CacheChange> {
+    let (rxref, cache) = {
+        let state = state.borrow();
+        let rxref = state.borrow::<Rc<RefCell<Option<broadcast::Receiver<CacheChange>>>>>();
+        (Rc::clone(rxref), data_cache(&state))
+    };
+    let mut rx = rxref.take().unwrap_or_else(|| cache.changes.subscribe());
+    let change = loop {
+        match rx.recv().await {
+            Ok(change) => break Some(change),
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break None,
+        }
+    };
+    rxref.replace(Some(rx));
+    return change;
+}
+
 #[op2(async)]
 async fn op_flush_cache(state: Rc<RefCell<OpState>>) -> () {
-    let state = state.borrow();
-    let txref = state.borrow::<Rc<RefCell<Option<mpsc::Sender<RouteRequest>>>>>();
-    let otxreq = txref.borrow_mut();
+    // the worker's own queue, which is gone once the app has been dropped
+    let otxreq = {
+        let state = state.borrow();
+        let txref = state.borrow::<Rc<RefCell<Option<mpsc::WeakSender<RouteRequest>>>>>();
+        let weak = txref.borrow();
+        weak.as_ref().and_then(|tx| tx.upgrade())
+    };
     //let (tx, rx) = oneshot::channel();
-    if let Some(txreq) = otxreq.as_ref() {
+    if let Some(txreq) = otxreq {
         let sendres = txreq
             .send(RouteRequest {
                 route_name: String::from("__create_cache"),
-                response_channel: None,
-                route_args: serde_json::Map::new(),
-                //request: req,
+                ..Default::default()
             })
             .await;
 
@@ -111,13 +313,275 @@ deno_core::extension!(
         op_create_cache,
         op_flush_cache,
         op_get_cache_value,
+        op_cache_version,
         op_get_cache_subset_value,
         op_with_cache,
+        op_subscribe_cache_changes,
+        op_next_cache_change,
+        op_update_cache,
+        op_patch_cache,
+        op_cache_stats,
     ],
-    js = ["src/extensions/datacache.js"]
+    js = ["src/extensions/datacache.js"],
+    state = |state: &mut OpState| {
+        let rx: Rc<RefCell<Option<broadcast::Receiver<CacheChange>>>> = Rc::new(RefCell::new(None));
+        state.put(rx);
+    }
 );
 
-pub fn set_data_cache(serde_val: Value) {
-    let mut cache = CACHE_VALUE_LOCK.write().unwrap();
-    *cache = serde_val;
+impl DataCache {
+    pub fn new() -> Arc<DataCache> {
+        return Arc::new(DataCache {
+            value: RwLock::new(CacheGeneration {
+                value: Value::Null,
+                version: 0,
+                hash: String::new(),
+                size_bytes: 0,
+            }),
+            persist_path: RwLock::new(None),
+            changes: broadcast::channel(16).0,
+            stats_path: RwLock::new(None),
+            stats: Mutex::new(CacheStats::default()),
+        });
+    }
+
+    pub fn set(&self, serde_val: Value) {
+        let mut cache = self.value.write().unwrap();
+        let change = self.publish_generation(&mut cache, serde_val);
+        self.after_publish(&cache, change);
+    }
+
+    // Applies `update` to a copy of the current value and publishes the result
+    // as a new generation. The write lock is held throughout, so concurrent
+    // updates from other isolates are never lost; on error nothing is published.
+    fn update(
+        &self,
+        update: impl FnOnce(&mut Value) -> Result<(), AnyError>,
+    ) -> Result<(), AnyError> {
+        let mut cache = self.value.write().unwrap();
+        let mut serde_val = cache.value.clone();
+        update(&mut serde_val)?;
+        let change = self.publish_generation(&mut cache, serde_val);
+        self.after_publish(&cache, change);
+        return Ok(());
+    }
+
+    fn publish_generation(
+        &self,
+        cache: &mut CacheGeneration,
+        serde_val: Value,
+    ) -> Option<CacheChange> {
+        let serialized = serde_val.to_string();
+        let hash = hash_serialized(&serialized);
+        let version = cache.version + 1;
+        let change = if self.changes.receiver_count() > 0 {
+            Some(diff_generations(&cache.value, &serde_val, version, &hash))
+        } else {
+            None
+        };
+        cache.hash = hash;
+        cache.version = version;
+        cache.size_bytes = serialized.len();
+        cache.value = serde_val;
+        return change;
+    }
+
+    // Called with the write lock still held, so snapshots and change events
+    // are queued in generation order; both sends are non-blocking.
+    fn after_publish(&self, cache: &CacheGeneration, change: Option<CacheChange>) {
+        let persist_path = self.persist_path.read().unwrap().clone();
+        if let Some(path) = persist_path {
+            let snapshot = PersistedCache {
+                version: CACHE_FILE_VERSION,
+                generation: cache.version,
+                hash: cache.hash.clone(),
+                data: cache.value.clone(),
+            };
+            // only fails when the writer thread is gone
+            let _ = persist_writer().send(PersistJob { path, snapshot });
+        }
+        if let Some(change) = change {
+            // only fails when nobody is subscribed anymore
+            let _ = self.changes.send(change);
+        }
+    }
+
+    // `text/event-stream` response emitting one `cache` event per published
+    // generation
+    pub fn events_response(&self, with_diff: bool) -> Response<Body> {
+        let rx = self.changes.subscribe();
+        let stream = futures::stream::unfold(rx, move |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(mut change) => {
+                        if !with_diff {
+                            change.diff = None;
+                        }
+                        let event = Event::default()
+                            .event("cache")
+                            .id(change.version.to_string())
+                            .json_data(&change)
+                            .unwrap();
+                        return Some((Ok::<_, Infallible>(event), rx));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        return Sse::new(stream)
+            .keep_alive(KeepAlive::default())
+            .into_response();
+    }
+
+    // Loads the snapshot written by a previous run, if persistence is enabled
+    // and the file is intact. Returns true when the cache was populated from
+    // disk.
+    pub fn load_persisted(&self) -> bool {
+        let persist_path = self.persist_path.read().unwrap().clone();
+        let path = match persist_path {
+            Some(path) => path,
+            None => return false,
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return false,
+        };
+        match serde_json::from_str::<PersistedCache>(&contents) {
+            Ok(snapshot)
+                if snapshot.version == CACHE_FILE_VERSION
+                    && snapshot.hash == cache_hash(&snapshot.data) =>
+            {
+                tracing::info!("Loaded cache snapshot from {}", path.display());
+                let mut cache = self.value.write().unwrap();
+                cache.size_bytes = snapshot.data.to_string().len();
+                cache.version = snapshot.generation;
+                cache.hash = snapshot.hash;
+                cache.value = snapshot.data;
+                return true;
+            }
+            _ => {
+                tracing::info!("Ignoring stale cache snapshot {}", path.display());
+                return false;
+            }
+        }
+    }
+
+    // ETag of the currently published generation, e.g. `"3-00ab12..."`
+    pub fn etag(&self) -> String {
+        let r1 = self.value.read().unwrap();
+        return generation_etag(&r1);
+    }
+
+    pub fn stats_path(&self) -> Option<String> {
+        return self.stats_path.read().unwrap().clone();
+    }
+
+    pub fn record_build(&self, duration: Duration, error: Option<String>) {
+        let mut stats = self.stats.lock().unwrap();
+        let ms = duration.as_secs_f64() * 1000.0;
+        stats.builds += 1;
+        stats.last_build_ms = Some(ms);
+        stats.total_build_ms += ms;
+        match error {
+            Some(e) => {
+                stats.failures += 1;
+                stats.last_error = Some(e);
+            }
+            None => {
+                stats.last_built_at = Some(chrono::Utc::now().to_rfc3339());
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Value {
+        let stats = self.stats.lock().unwrap().clone();
+        let cache = self.value.read().unwrap();
+        let mut res = serde_json::to_value(stats).unwrap();
+        res["generation"] = cache.version.into();
+        res["sizeBytes"] = cache.size_bytes.into();
+        res["hash"] = cache.hash.clone().into();
+        return res;
+    }
+
+    fn count_key_reads<'a>(&self, keys: impl IntoIterator<Item = &'a str>) {
+        let mut stats = self.stats.lock().unwrap();
+        for key in keys {
+            *stats.key_reads.entry(String::from(key)).or_insert(0) += 1;
+        }
+    }
+}
+
+fn diff_generations(old: &Value, new: &Value, version: u64, hash: &str) -> CacheChange {
+    let (changed, diff) = match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let mut diff = serde_json::Map::new();
+            for (key, value) in new {
+                if old.get(key) != Some(value) {
+                    diff.insert(key.clone(), value.clone());
+                }
+            }
+            for key in old.keys() {
+                if !new.contains_key(key) {
+                    diff.insert(key.clone(), Value::Null);
+                }
+            }
+            (diff.keys().cloned().collect(), Some(diff))
+        }
+        _ => (vec![], None),
+    };
+    CacheChange {
+        version,
+        hash: String::from(hash),
+        changed,
+        diff,
+    }
+}
+
+// Snapshots are written on a thread of their own so the JS workers do not
+// block on the disk; when several generations of a file queue up only the
+// newest is written.
+fn persist_writer() -> &'static mpsc::UnboundedSender<PersistJob> {
+    return CACHE_PERSIST_WRITER.get_or_init(|| {
+        let (tx, mut rx) = mpsc::unbounded_channel::<PersistJob>();
+        thread::Builder::new()
+            .name(String::from("cache-persist"))
+            .spawn(move || {
+                while let Some(job) = rx.blocking_recv() {
+                    let mut jobs = vec![job];
+                    while let Ok(newer) = rx.try_recv() {
+                        jobs.retain(|job| job.path != newer.path);
+                        jobs.push(newer);
+                    }
+                    for job in jobs {
+                        if let Err(e) = persist_data_cache(&job.path, &job.snapshot) {
+                            let path = job.path.display();
+                            tracing::warn!("Could not persist cache to {}: {}", path, e);
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        tx
+    });
+}
+
+fn persist_data_cache(path: &Path, snapshot: &PersistedCache) -> std::io::Result<()> {
+    // write next to the target and rename so readers never see a partial file
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_vec(snapshot)?)?;
+    fs::rename(&tmp_path, path)
+}
+
+pub fn cache_hash(serde_val: &Value) -> String {
+    return hash_serialized(&serde_val.to_string());
+}
+
+// sha256, so persisted snapshots stay valid across toolchain upgrades
+fn hash_serialized(serialized: &str) -> String {
+    format!("{:x}", Sha256::digest(serialized.as_bytes()))
+}
+
+fn generation_etag(generation: &CacheGeneration) -> String {
+    format!("\"{}-{}\"", generation.version, generation.hash)
 }
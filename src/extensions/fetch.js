@@ -0,0 +1,204 @@
+((globalThis) => {
+  const core = Deno.core;
+  const { readStream, toBytes } = globalThis.__bodyChunks;
+
+  class Headers {
+    #map = new Map();
+
+    constructor(init) {
+      if (init == null) return;
+      const entries =
+        init instanceof Headers || Array.isArray(init)
+          ? init
+          : typeof init[Symbol.iterator] === "function"
+          ? init
+          : Object.entries(init);
+      for (const [name, value] of entries) this.append(name, value);
+    }
+
+    append(name, value) {
+      const key = String(name).toLowerCase();
+      const prev = this.#map.get(key);
+      this.#map.set(key, prev === undefined ? String(value) : `${prev}, ${value}`);
+    }
+
+    set(name, value) {
+      this.#map.set(String(name).toLowerCase(), String(value));
+    }
+
+    get(name) {
+      return this.#map.get(String(name).toLowerCase()) ?? null;
+    }
+
+    has(name) {
+      return this.#map.has(String(name).toLowerCase());
+    }
+
+    delete(name) {
+      this.#map.delete(String(name).toLowerCase());
+    }
+
+    forEach(callback, thisArg) {
+      for (const [name, value] of this) callback.call(thisArg, value, name, this);
+    }
+
+    *entries() {
+      yield* [...this.#map.entries()].sort(([a], [b]) => (a < b ? -1 : 1));
+    }
+
+    *keys() {
+      for (const [name] of this.entries()) yield name;
+    }
+
+    *values() {
+      for (const [, value] of this.entries()) yield value;
+    }
+
+    [Symbol.iterator]() {
+      return this.entries();
+    }
+  }
+
+  function concat(chunks) {
+    const out = new Uint8Array(chunks.reduce((n, c) => n + c.byteLength, 0));
+    let offset = 0;
+    for (const chunk of chunks) {
+      out.set(chunk, offset);
+      offset += chunk.byteLength;
+    }
+    return out;
+  }
+
+  // Responses whose body is never read would keep their connection in the
+  // fetch table, so it is closed once the Response is garbage collected.
+  const unread = new FinalizationRegistry((close) => close());
+
+  // The body is an async iterable of Uint8Array chunks read from Rust on
+  // demand, so it can also be returned as a streamed response.
+  class Response {
+    #id;
+    #signal;
+    #close;
+    bodyUsed = false;
+
+    constructor(id, init, signal, close) {
+      this.#id = id;
+      this.#signal = signal;
+      this.#close = close;
+      this.status = init.status;
+      this.statusText = init.statusText;
+      this.ok = init.status >= 200 && init.status < 300;
+      this.url = init.url;
+      this.headers = new Headers(init.headers);
+      unread.register(this, close, this);
+    }
+
+    get body() {
+      if (this.bodyUsed) throw new TypeError("body has already been read");
+      this.bodyUsed = true;
+      return this.#chunks();
+    }
+
+    async *#chunks() {
+      try {
+        while (true) {
+          const chunk = await core.ops.op_fetch_read(this.#id);
+          if (chunk === null) return;
+          yield chunk;
+        }
+      } catch (e) {
+        throw this.#signal?.aborted ? this.#signal.reason : e;
+      } finally {
+        unread.unregister(this);
+        this.#close();
+      }
+    }
+
+    async bytes() {
+      const chunks = [];
+      for await (const chunk of this.body) chunks.push(chunk);
+      return concat(chunks);
+    }
+
+    async arrayBuffer() {
+      return (await this.bytes()).buffer;
+    }
+
+    async text() {
+      return core.decode(await this.bytes());
+    }
+
+    async json() {
+      return JSON.parse(await this.text());
+    }
+  }
+
+  function isStreamBody(body) {
+    return (
+      typeof body[Symbol.asyncIterator] === "function" ||
+      typeof body.getReader === "function"
+    );
+  }
+
+  const unsupported = () => {
+    throw new TypeError("unsupported fetch body");
+  };
+
+  async function pumpBody(id, body) {
+    const iterable = typeof body.getReader === "function" ? readStream(body) : body;
+    try {
+      for await (const chunk of iterable) {
+        const bytes = toBytes(chunk, unsupported);
+        if (!(await core.ops.op_fetch_body_write(id, bytes))) break;
+      }
+    } catch (e) {
+      console.error("fetch request body failed", String(e));
+    } finally {
+      core.ops.op_fetch_body_close(id);
+    }
+  }
+
+  globalThis.fetch = async (input, init = {}) => {
+    const url = typeof input === "string" ? input : String(input.url ?? input);
+    const signal = init.signal;
+    signal?.throwIfAborted();
+
+    const headers = new Headers(init.headers);
+    let body = init.body ?? null;
+    if (typeof body === "string" && !headers.has("content-type")) {
+      headers.set("content-type", "text/plain;charset=UTF-8");
+    }
+    const streamBody = body !== null && typeof body !== "string" && isStreamBody(body);
+
+    const id = core.ops.op_fetch_start();
+    const abort = () => core.ops.op_fetch_abort(id);
+    signal?.addEventListener("abort", abort);
+    // once the body is read or the request failed
+    const close = () => {
+      signal?.removeEventListener("abort", abort);
+      core.ops.op_fetch_close(id);
+    };
+    if (streamBody) {
+      core.ops.op_fetch_body_create(id);
+      pumpBody(id, body);
+      body = null;
+    }
+    try {
+      const res = await core.ops.op_fetch(id, {
+        method: init.method ?? "GET",
+        url,
+        headers: [...headers],
+        body: body === null ? null : toBytes(body, unsupported),
+        streamBody,
+        timeout: init.timeout ?? null,
+        context: init.context ?? 0,
+      });
+      return new Response(id, res, signal, close);
+    } catch (e) {
+      close();
+      throw signal?.aborted ? signal.reason : new TypeError(e.message);
+    }
+  };
+
+  globalThis.Headers = Headers;
+})(globalThis);
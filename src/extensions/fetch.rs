@@ -0,0 +1,356 @@
+use crate::body::STREAM_CHUNKS;
+use crate::config::AppConfig;
+use crate::logging::request_span;
+use crate::telemetry::trace_headers;
+use axum::body::Bytes;
+use deno_core::anyhow::{anyhow, bail};
+use deno_core::error::AnyError;
+use deno_core::op2;
+use deno_core::url::Url;
+use deno_core::{JsBuffer, OpState, ToJsBuffer};
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::Duration;
+use tracing::Instrument;
+
+// `fetch: { allow: ["api.internal:8080", "*.example.com"], timeout: 5000 }`
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FetchConfig {
+    // any host may be fetched when unset
+    pub allow: Option<Vec<String>>,
+    // milliseconds, for the whole request including the body
+    pub timeout: Option<u64>,
+}
+
+// One entry per `fetch()` call, keyed by the id from `op_fetch_start`. The
+// cancel handle is notified when the call's AbortSignal fires.
+#[derive(Default)]
+struct FetchTable {
+    next_id: u32,
+    cancels: HashMap<u32, Rc<Notify>>,
+    request_senders: HashMap<u32, mpsc::Sender<Bytes>>,
+    request_receivers: HashMap<u32, mpsc::Receiver<Bytes>>,
+    responses: HashMap<u32, BoxStream<'static, reqwest::Result<Bytes>>>,
+    client: Option<FetchClient>,
+}
+
+// built for the allow list it checks redirects against
+struct FetchClient {
+    allow: Option<Vec<String>>,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FetchRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<JsBuffer>,
+    // the body is written with `op_fetch_body_write` while the request runs
+    stream_body: bool,
+    timeout: Option<u64>,
+    // of the request the fetch is made for, see `req.fetch`
+    context: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FetchResponse {
+    status: u16,
+    status_text: String,
+    url: String,
+    headers: Vec<(String, String)>,
+}
+
+fn host_allowed(allow: &[String], url: &Url) -> bool {
+    let host = url.host_str().unwrap_or("");
+    let port = url.port_or_known_default();
+    return allow.iter().any(|entry| {
+        let (pattern, entry_port) = match entry.rsplit_once(':') {
+            Some((pattern, port)) => (pattern, port.parse::<u16>().ok()),
+            None => (entry.as_str(), None),
+        };
+        let host_matches = match pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .to_ascii_lowercase()
+                .ends_with(&format!(".{}", domain.to_ascii_lowercase())),
+            None => host.eq_ignore_ascii_case(pattern),
+        };
+        host_matches && (entry_port.is_none() || entry_port == port)
+    });
+}
+
+const MAX_REDIRECTS: usize = 10;
+
+// Every redirect hop is checked like the first URL, so an allowed upstream
+// can't send the request on to a host outside `fetch.allow`.
+fn redirect_policy(allow: Option<Vec<String>>) -> reqwest::redirect::Policy {
+    return reqwest::redirect::Policy::custom(move |attempt| {
+        let url = attempt.url();
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        if !matches!(url.scheme(), "http" | "https") {
+            let e = format!("fetch only supports http and https URLs, got {}", url);
+            return attempt.error(e);
+        }
+        if let Some(allow) = &allow {
+            if !host_allowed(allow, url) {
+                let e = format!(
+                    "redirect to {} is not allowed",
+                    url.host_str().unwrap_or("")
+                );
+                return attempt.error(e);
+            }
+        }
+        return attempt.follow();
+    });
+}
+
+// the policy's reason rather than reqwest's "error following redirect"
+fn redirect_error(e: reqwest::Error) -> AnyError {
+    if e.is_redirect() {
+        if let Some(source) = std::error::Error::source(&e) {
+            return anyhow!("{}", source);
+        }
+    }
+    return e.into();
+}
+
+fn fetch_client(state: &OpState, allow: &Option<Vec<String>>) -> Result<reqwest::Client, AnyError> {
+    let table = fetch_table(state);
+    let mut table = table.borrow_mut();
+    if let Some(cached) = &table.client {
+        if &cached.allow == allow {
+            return Ok(cached.client.clone());
+        }
+    }
+    let client = reqwest::Client::builder()
+        .redirect(redirect_policy(allow.clone()))
+        .build()?;
+    table.client = Some(FetchClient {
+        allow: allow.clone(),
+        client: client.clone(),
+    });
+    return Ok(client);
+}
+
+fn fetch_table(state: &OpState) -> Rc<RefCell<FetchTable>> {
+    return Rc::clone(state.borrow::<Rc<RefCell<FetchTable>>>());
+}
+
+fn cancel_handle(state: &Rc<RefCell<OpState>>, id: u32) -> Rc<Notify> {
+    let table = fetch_table(&state.borrow());
+    let cancel = table.borrow().cancels.get(&id).cloned();
+    return cancel.unwrap_or_else(|| Rc::new(Notify::new()));
+}
+
+#[op2(fast)]
+fn op_fetch_start(state: &mut OpState) -> u32 {
+    let table = fetch_table(state);
+    let mut table = table.borrow_mut();
+    table.next_id += 1;
+    let id = table.next_id;
+    table.cancels.insert(id, Rc::new(Notify::new()));
+    return id;
+}
+
+#[op2(fast)]
+fn op_fetch_body_create(state: &mut OpState, id: u32) {
+    let table = fetch_table(state);
+    let mut table = table.borrow_mut();
+    let (tx, rx) = mpsc::channel(STREAM_CHUNKS);
+    table.request_senders.insert(id, tx);
+    table.request_receivers.insert(id, rx);
+}
+
+// resolves to false once the request has failed or was aborted
+#[op2(async)]
+async fn op_fetch_body_write(
+    state: Rc<RefCell<OpState>>,
+    id: u32,
+    #[buffer] chunk: JsBuffer,
+) -> bool {
+    let sender = {
+        let table = fetch_table(&state.borrow());
+        let sender = table.borrow().request_senders.get(&id).cloned();
+        sender
+    };
+    match sender {
+        Some(sender) => sender.send(Bytes::from(chunk.to_vec())).await.is_ok(),
+        None => false,
+    }
+}
+
+#[op2(fast)]
+fn op_fetch_body_close(state: &mut OpState, id: u32) {
+    let table = fetch_table(state);
+    table.borrow_mut().request_senders.remove(&id);
+}
+
+#[op2(async)]
+#[serde]
+async fn op_fetch(
+    state: Rc<RefCell<OpState>>,
+    id: u32,
+    #[serde] req: FetchRequest,
+) -> Result<FetchResponse, AnyError> {
+    let (client, config, parent) = {
+        let state = state.borrow();
+        let config = state
+            .borrow::<Rc<RefCell<AppConfig>>>()
+            .borrow()
+            .fetch
+            .clone();
+        let parent = request_span(&state, req.context);
+        (fetch_client(&state, &config.allow)?, config, parent)
+    };
+    let url = Url::parse(&req.url)?;
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("fetch only supports http and https URLs, got {}", url);
+    }
+    if let Some(allow) = &config.allow {
+        if !host_allowed(allow, &url) {
+            bail!("fetch to {} is not allowed", url.host_str().unwrap_or(""));
+        }
+    }
+
+    let method = reqwest::Method::from_bytes(req.method.to_uppercase().as_bytes())?;
+    let span = tracing::info_span!(
+        parent: parent.id(),
+        "fetch",
+        otel.kind = "client",
+        method = %method,
+        url = %url,
+        status = tracing::field::Empty,
+    );
+    let mut builder = client.request(method, url);
+    for (name, value) in &req.headers {
+        builder = builder.header(name, value);
+    }
+    // the upstream continues this request's trace
+    for (name, value) in trace_headers(&span) {
+        builder = builder.header(name, value);
+    }
+    if let Some(ms) = req.timeout.or(config.timeout) {
+        builder = builder.timeout(Duration::from_millis(ms));
+    }
+    if req.stream_body {
+        let table = fetch_table(&state.borrow());
+        let receiver = table.borrow_mut().request_receivers.remove(&id);
+        if let Some(receiver) = receiver {
+            let body = futures::stream::unfold(receiver, |mut rx| async move {
+                let chunk = rx.recv().await?;
+                Some((Ok::<_, std::io::Error>(chunk), rx))
+            });
+            builder = builder.body(reqwest::Body::wrap_stream(body));
+        }
+    } else if let Some(body) = req.body {
+        builder = builder.body(body.to_vec());
+    }
+
+    let cancel = cancel_handle(&state, id);
+    let resp = tokio::select! {
+        resp = builder.send().instrument(span.clone()) => resp.map_err(redirect_error)?,
+        _ = cancel.notified() => bail!("fetch was aborted"),
+    };
+    let status = resp.status();
+    span.record("status", status.as_u16());
+    let headers = resp
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            (String::from(name.as_str()), value)
+        })
+        .collect();
+    let res = FetchResponse {
+        status: status.as_u16(),
+        status_text: String::from(status.canonical_reason().unwrap_or("")),
+        url: resp.url().to_string(),
+        headers,
+    };
+    let table = fetch_table(&state.borrow());
+    table
+        .borrow_mut()
+        .responses
+        .insert(id, resp.bytes_stream().boxed());
+    return Ok(res);
+}
+
+// the next chunk of the response body, null at the end
+#[op2(async)]
+#[serde]
+async fn op_fetch_read(
+    state: Rc<RefCell<OpState>>,
+    id: u32,
+) -> Result<Option<ToJsBuffer>, AnyError> {
+    let stream = {
+        let table = fetch_table(&state.borrow());
+        let stream = table.borrow_mut().responses.remove(&id);
+        stream
+    };
+    let Some(mut stream) = stream else {
+        return Ok(None);
+    };
+    let cancel = cancel_handle(&state, id);
+    let chunk = tokio::select! {
+        chunk = stream.next() => chunk,
+        _ = cancel.notified() => return Err(anyhow!("fetch was aborted")),
+    };
+    match chunk {
+        Some(Ok(chunk)) => {
+            let table = fetch_table(&state.borrow());
+            table.borrow_mut().responses.insert(id, stream);
+            Ok(Some(ToJsBuffer::from(chunk.to_vec())))
+        }
+        Some(Err(e)) => Err(e.into()),
+        None => Ok(None),
+    }
+}
+
+#[op2(fast)]
+fn op_fetch_abort(state: &mut OpState, id: u32) {
+    let table = fetch_table(state);
+    let cancel = table.borrow().cancels.get(&id).cloned();
+    if let Some(cancel) = cancel {
+        // between two body reads, the next read picks the abort up
+        cancel.notify_one();
+    }
+}
+
+#[op2(fast)]
+fn op_fetch_close(state: &mut OpState, id: u32) {
+    let table = fetch_table(state);
+    let mut table = table.borrow_mut();
+    table.cancels.remove(&id);
+    table.request_senders.remove(&id);
+    table.request_receivers.remove(&id);
+    table.responses.remove(&id);
+}
+
+deno_core::extension!(
+    fetch_extension,
+    ops = [
+        op_fetch_start,
+        op_fetch_body_create,
+        op_fetch_body_write,
+        op_fetch_body_close,
+        op_fetch,
+        op_fetch_read,
+        op_fetch_abort,
+        op_fetch_close,
+    ],
+    js = ["src/extensions/fetch.js"],
+    state = |state: &mut OpState| {
+        let table: Rc<RefCell<FetchTable>> = Rc::new(RefCell::new(FetchTable::default()));
+        state.put(table);
+    }
+);
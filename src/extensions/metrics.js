@@ -0,0 +1,30 @@
+((globalThis) => {
+  const core = Deno.core;
+
+  // inc(), inc(2), inc({ route: "/a" }), inc({ route: "/a" }, 2)
+  function labelsAndValue(labels, value, defaultValue) {
+    if (typeof labels === "number") return [{}, labels];
+    return [labels ?? {}, value ?? defaultValue];
+  }
+
+  // Shown on the `metrics` path of `configure()` with the server's own
+  // metrics, summed over all workers.
+  globalThis.metrics = {
+    counter(name, { help = name, labels = [] } = {}) {
+      core.ops.op_metric_register("counter", name, help, labels, null);
+      return {
+        inc: (labelValues, value) =>
+          core.ops.op_metric_inc(name, ...labelsAndValue(labelValues, value, 1)),
+      };
+    },
+
+    histogram(name, { help = name, labels = [], buckets = null } = {}) {
+      core.ops.op_metric_register("histogram", name, help, labels, buckets);
+      return {
+        // observe(0.25) or observe({ kind: "a" }, 0.25)
+        observe: (labelValues, value) =>
+          core.ops.op_metric_observe(name, ...labelsAndValue(labelValues, value)),
+      };
+    },
+  };
+})(globalThis);
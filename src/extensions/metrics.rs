@@ -0,0 +1,147 @@
+use crate::metrics::metrics;
+use deno_core::anyhow::{anyhow, bail};
+use deno_core::error::AnyError;
+use deno_core::op2;
+use prometheus::{CounterVec, HistogramOpts, HistogramVec, Opts, DEFAULT_BUCKETS};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+// Metrics created from JS. Every worker runs the setup file, so creating a
+// metric that already exists with the same labels and buckets returns the
+// existing one.
+#[derive(Clone)]
+enum JsMetric {
+    Counter(CounterVec),
+    Histogram(HistogramVec),
+}
+
+struct RegisteredMetric {
+    metric: JsMetric,
+    labels: Vec<String>,
+    // None for counters
+    buckets: Option<Vec<f64>>,
+}
+
+static JS_METRICS: OnceLock<Mutex<HashMap<String, RegisteredMetric>>> = OnceLock::new();
+
+fn js_metrics() -> &'static Mutex<HashMap<String, RegisteredMetric>> {
+    return JS_METRICS.get_or_init(|| Mutex::new(HashMap::new()));
+}
+
+fn js_metric(name: &str) -> Result<JsMetric, AnyError> {
+    let metrics = js_metrics().lock().unwrap();
+    return metrics
+        .get(name)
+        .map(|registered| registered.metric.clone())
+        .ok_or_else(|| anyhow!("metric {} is not registered", name));
+}
+
+#[op2]
+fn op_metric_register(
+    #[string] kind: String,
+    #[string] name: String,
+    #[string] help: String,
+    #[serde] labels: Vec<String>,
+    #[serde] buckets: Option<Vec<f64>>,
+) -> Result<(), AnyError> {
+    let buckets = match kind.as_str() {
+        "counter" => None,
+        "histogram" => Some(buckets.unwrap_or_else(|| DEFAULT_BUCKETS.to_vec())),
+        _ => bail!("unknown metric type {}", kind),
+    };
+    let mut metrics = js_metrics().lock().unwrap();
+    if let Some(registered) = metrics.get(&name) {
+        match (&registered.metric, kind.as_str()) {
+            (JsMetric::Counter(_), "counter") | (JsMetric::Histogram(_), "histogram") => {}
+            _ => bail!("metric {} is already registered with another type", name),
+        }
+        if registered.labels != labels {
+            bail!(
+                "metric {} is already registered with the labels {:?}",
+                name,
+                registered.labels
+            );
+        }
+        if registered.buckets != buckets {
+            bail!(
+                "metric {} is already registered with the buckets {:?}",
+                name,
+                registered.buckets.as_deref().unwrap_or_default()
+            );
+        }
+        return Ok(());
+    }
+    let label_names = labels
+        .iter()
+        .map(|label| label.as_str())
+        .collect::<Vec<_>>();
+    let metric = match &buckets {
+        None => JsMetric::Counter(CounterVec::new(Opts::new(&name, &help), &label_names)?),
+        Some(buckets) => {
+            let opts = HistogramOpts::new(&name, &help).buckets(buckets.clone());
+            JsMetric::Histogram(HistogramVec::new(opts, &label_names)?)
+        }
+    };
+    let registry = &metrics().registry;
+    match &metric {
+        JsMetric::Counter(counter) => registry.register(Box::new(counter.clone()))?,
+        JsMetric::Histogram(histogram) => registry.register(Box::new(histogram.clone()))?,
+    }
+    metrics.insert(
+        name,
+        RegisteredMetric {
+            metric,
+            labels,
+            buckets,
+        },
+    );
+    return Ok(());
+}
+
+fn label_refs(labels: &HashMap<String, String>) -> HashMap<&str, &str> {
+    return labels
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+}
+
+#[op2]
+fn op_metric_inc(
+    #[string] name: String,
+    #[serde] labels: HashMap<String, String>,
+    value: f64,
+) -> Result<(), AnyError> {
+    let JsMetric::Counter(counter) = js_metric(&name)? else {
+        bail!("metric {} is not a counter", name);
+    };
+    // a counter only goes up; prometheus would take anything
+    if !value.is_finite() || value < 0.0 {
+        bail!("counter {} can't be increased by {}", name, value);
+    }
+    counter.get_metric_with(&label_refs(&labels))?.inc_by(value);
+    return Ok(());
+}
+
+#[op2]
+fn op_metric_observe(
+    #[string] name: String,
+    #[serde] labels: HashMap<String, String>,
+    value: f64,
+) -> Result<(), AnyError> {
+    let JsMetric::Histogram(histogram) = js_metric(&name)? else {
+        bail!("metric {} is not a histogram", name);
+    };
+    if !value.is_finite() {
+        bail!("histogram {} can't observe {}", name, value);
+    }
+    histogram
+        .get_metric_with(&label_refs(&labels))?
+        .observe(value);
+    return Ok(());
+}
+
+deno_core::extension!(
+    metrics_extension,
+    ops = [op_metric_register, op_metric_inc, op_metric_observe],
+    js = ["src/extensions/metrics.js"]
+);
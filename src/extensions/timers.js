@@ -0,0 +1,81 @@
+((globalThis) => {
+  const core = Deno.core;
+
+  const MAX_DELAY = 2 ** 31 - 1;
+
+  // id -> { promise, refed } of the timers that have not fired or been cleared
+  const activeTimers = new Map();
+
+  function normalizeDelay(delay) {
+    delay = Number(delay);
+    if (!Number.isFinite(delay) || delay < 0) return 0;
+    return Math.min(Math.trunc(delay), MAX_DELAY);
+  }
+
+  function wait(id, delay) {
+    const timer = activeTimers.get(id);
+    timer.promise = core.ops.op_timer_wait(id, delay);
+    if (!timer.refed) core.unrefOpPromise(timer.promise);
+    return timer.promise;
+  }
+
+  function runCallback(callback, args) {
+    try {
+      callback(...args);
+    } catch (e) {
+      console.error("timer callback failed", String(e?.stack ?? e));
+    }
+  }
+
+  function startTimer(callback, delay, args, repeat) {
+    if (typeof callback !== "function") {
+      throw new TypeError("timer callback must be a function");
+    }
+    delay = normalizeDelay(delay);
+    const id = core.ops.op_timer_create();
+    activeTimers.set(id, { promise: null, refed: true });
+    (async () => {
+      do {
+        if (!(await wait(id, delay)) || !activeTimers.has(id)) return;
+        if (!repeat) clearTimer(id);
+        runCallback(callback, args);
+      } while (repeat && activeTimers.has(id));
+    })();
+    return id;
+  }
+
+  function clearTimer(id) {
+    if (!activeTimers.delete(id)) return;
+    core.ops.op_timer_clear(id);
+  }
+
+  globalThis.setTimeout = (callback, delay = 0, ...args) =>
+    startTimer(callback, delay, args, false);
+  globalThis.setInterval = (callback, delay = 0, ...args) =>
+    startTimer(callback, delay, args, true);
+  globalThis.clearTimeout = clearTimer;
+  globalThis.clearInterval = clearTimer;
+
+  globalThis.queueMicrotask = (callback) => {
+    if (typeof callback !== "function") {
+      throw new TypeError("queueMicrotask callback must be a function");
+    }
+    Promise.resolve().then(() => runCallback(callback, []));
+  };
+
+  // An unref'd timer still fires, but does not keep the event loop alive on
+  // its own, e.g. while the setup file is being evaluated.
+  Deno.unrefTimer = (id) => {
+    const timer = activeTimers.get(id);
+    if (!timer || !timer.refed) return;
+    timer.refed = false;
+    if (timer.promise) core.unrefOpPromise(timer.promise);
+  };
+
+  Deno.refTimer = (id) => {
+    const timer = activeTimers.get(id);
+    if (!timer || timer.refed) return;
+    timer.refed = true;
+    if (timer.promise) core.refOpPromise(timer.promise);
+  };
+})(globalThis);
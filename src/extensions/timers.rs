@@ -0,0 +1,61 @@
+use deno_core::op2;
+use deno_core::OpState;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use tokio::sync::Notify;
+use tokio::time::{sleep, Duration};
+
+// cancellation handles of the timers that have not been cleared yet
+#[derive(Default)]
+struct TimerTable {
+    next_id: u32,
+    cancels: HashMap<u32, Rc<Notify>>,
+}
+
+#[op2(fast)]
+fn op_timer_create(state: &mut OpState) -> u32 {
+    let table = state.borrow::<Rc<RefCell<TimerTable>>>();
+    let mut table = table.borrow_mut();
+    table.next_id += 1;
+    let id = table.next_id;
+    table.cancels.insert(id, Rc::new(Notify::new()));
+    return id;
+}
+
+// resolves to true when the delay elapsed and to false once the timer is cleared
+#[op2(async)]
+async fn op_timer_wait(state: Rc<RefCell<OpState>>, id: u32, delay: u32) -> bool {
+    let cancel = {
+        let state = state.borrow();
+        let table = state.borrow::<Rc<RefCell<TimerTable>>>().borrow();
+        table.cancels.get(&id).cloned()
+    };
+    let Some(cancel) = cancel else {
+        return false;
+    };
+    tokio::select! {
+        _ = sleep(Duration::from_millis(delay.into())) => true,
+        _ = cancel.notified() => false,
+    }
+}
+
+#[op2(fast)]
+fn op_timer_clear(state: &mut OpState, id: u32) {
+    let table = state.borrow::<Rc<RefCell<TimerTable>>>();
+    let cancel = table.borrow_mut().cancels.remove(&id);
+    if let Some(cancel) = cancel {
+        // stores a permit if the timer is not waiting right now
+        cancel.notify_one();
+    }
+}
+
+deno_core::extension!(
+    timers_extension,
+    ops = [op_timer_create, op_timer_wait, op_timer_clear],
+    js = ["src/extensions/timers.js"],
+    state = |state: &mut OpState| {
+        let table: Rc<RefCell<TimerTable>> = Rc::new(RefCell::new(TimerTable::default()));
+        state.put(table);
+    }
+);
@@ -0,0 +1,71 @@
+((globalThis) => {
+  const core = Deno.core;
+
+  const OPEN = 1;
+  const CLOSING = 2;
+  const CLOSED = 3;
+
+  class ScriptWebSocket {
+    #id;
+
+    constructor(id) {
+      this.#id = id;
+      this.readyState = OPEN;
+      this.onmessage = null;
+      this.onclose = null;
+    }
+
+    send(data) {
+      if (this.readyState !== OPEN) {
+        throw new Error("WebSocket is not open");
+      }
+      if (typeof data === "string") {
+        return Deno.core.ops.op_ws_send_text(this.#id, data);
+      }
+      const bytes =
+        data instanceof ArrayBuffer
+          ? new Uint8Array(data)
+          : new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+      return Deno.core.ops.op_ws_send_binary(this.#id, bytes);
+    }
+
+    close(code = 1000, reason = "") {
+      if (this.readyState >= CLOSING) return;
+      this.readyState = CLOSING;
+      return Deno.core.ops.op_ws_close(this.#id, code, reason);
+    }
+
+    async listen() {
+      while (true) {
+        const event = await Deno.core.ops.op_ws_next(this.#id);
+        if (!event || event.kind === "close") {
+          this.readyState = CLOSED;
+          Deno.core.ops.op_ws_unregister(this.#id);
+          await this.onclose?.({
+            code: event?.code ?? 1006,
+            reason: event?.reason ?? "",
+          });
+          return;
+        }
+        try {
+          await this.onmessage?.({ data: event.data });
+        } catch (e) {
+          console.error("websocket onmessage failed", String(e));
+        }
+      }
+    }
+  }
+
+  globalThis.websocket = (path, options, handler) => {
+    if (typeof options === "function") {
+      handler = options;
+      options = {};
+    }
+    globalThis.route(path, { ...options, websocket: true }, (req) => {
+      const socket = new ScriptWebSocket(req.socket);
+      const handled = handler(socket, req);
+      socket.listen();
+      return handled;
+    });
+  };
+})(globalThis);
@@ -0,0 +1,265 @@
+use crate::logging::request_id;
+use crate::routing::{headers_to_json, query_to_json, route_key, RouteRequest, RouteState};
+use axum::body::Body;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{MatchedPath, RawPathParams, State};
+use axum::http::{HeaderMap, Uri};
+use axum::response::Response;
+use deno_core::error::AnyError;
+use deno_core::op2;
+use deno_core::{JsBuffer, OpState, ToJsBuffer};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use tokio::sync::mpsc;
+use tokio::time::{interval, timeout, Duration};
+
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+// how long a close initiated by JS waits for the peer's close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+    Close(u16, String),
+}
+
+// the worker's half of an upgraded connection
+pub struct WebSocketChannels {
+    pub incoming: mpsc::Receiver<WsMessage>,
+    pub outgoing: mpsc::Sender<WsMessage>,
+}
+
+struct WebSocketConn {
+    incoming: Rc<tokio::sync::Mutex<mpsc::Receiver<WsMessage>>>,
+    outgoing: mpsc::Sender<WsMessage>,
+}
+
+#[derive(Default)]
+struct WebSocketTable {
+    next_id: u32,
+    sockets: HashMap<u32, WebSocketConn>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum WsEvent {
+    Text { data: String },
+    Binary { data: ToJsBuffer },
+    Close { code: u16, reason: String },
+}
+
+fn socket_outgoing(state: &Rc<RefCell<OpState>>, id: u32) -> Option<mpsc::Sender<WsMessage>> {
+    let state = state.borrow();
+    let table = state.borrow::<Rc<RefCell<WebSocketTable>>>().borrow();
+    table.sockets.get(&id).map(|conn| conn.outgoing.clone())
+}
+
+#[op2(async)]
+#[serde]
+async fn op_ws_next(state: Rc<RefCell<OpState>>, id: u32) -> Option<WsEvent> {
+    let incoming = {
+        let state = state.borrow();
+        let table = state.borrow::<Rc<RefCell<WebSocketTable>>>().borrow();
+        table.sockets.get(&id).map(|conn| Rc::clone(&conn.incoming))
+    };
+    let incoming = incoming?;
+    let msg = incoming.lock().await.recv().await;
+    return match msg {
+        Some(WsMessage::Text(data)) => Some(WsEvent::Text { data }),
+        Some(WsMessage::Binary(data)) => Some(WsEvent::Binary { data: data.into() }),
+        Some(WsMessage::Close(code, reason)) => Some(WsEvent::Close { code, reason }),
+        None => Some(WsEvent::Close {
+            code: 1006,
+            reason: String::new(),
+        }),
+    };
+}
+
+#[op2(async)]
+async fn op_ws_send_text(
+    state: Rc<RefCell<OpState>>,
+    id: u32,
+    #[string] data: String,
+) -> Result<(), AnyError> {
+    if let Some(outgoing) = socket_outgoing(&state, id) {
+        outgoing.send(WsMessage::Text(data)).await?;
+    }
+    return Ok(());
+}
+
+#[op2(async)]
+async fn op_ws_send_binary(
+    state: Rc<RefCell<OpState>>,
+    id: u32,
+    #[buffer] data: JsBuffer,
+) -> Result<(), AnyError> {
+    if let Some(outgoing) = socket_outgoing(&state, id) {
+        outgoing.send(WsMessage::Binary(data.to_vec())).await?;
+    }
+    return Ok(());
+}
+
+#[op2(async)]
+async fn op_ws_close(
+    state: Rc<RefCell<OpState>>,
+    id: u32,
+    code: u16,
+    #[string] reason: String,
+) -> () {
+    if let Some(outgoing) = socket_outgoing(&state, id) {
+        // the connection may already be gone
+        let _ = outgoing.send(WsMessage::Close(code, reason)).await;
+    }
+}
+
+#[op2(fast)]
+fn op_ws_unregister(state: &mut OpState, id: u32) {
+    let table = state.borrow::<Rc<RefCell<WebSocketTable>>>();
+    table.borrow_mut().sockets.remove(&id);
+}
+
+deno_core::extension!(
+    websocket_extension,
+    ops = [
+        op_ws_next,
+        op_ws_send_text,
+        op_ws_send_binary,
+        op_ws_close,
+        op_ws_unregister,
+    ],
+    js = ["src/extensions/websocket.js"],
+    state = |state: &mut OpState| {
+        let table: Rc<RefCell<WebSocketTable>> = Rc::new(RefCell::new(WebSocketTable::default()));
+        state.put(table);
+    }
+);
+
+// Hands an upgraded connection to the JS worker and returns the id the
+// `socket` object uses to talk to it.
+pub fn register_websocket(state: &OpState, channels: WebSocketChannels) -> u32 {
+    let table = state.borrow::<Rc<RefCell<WebSocketTable>>>();
+    let mut table = table.borrow_mut();
+    table.next_id += 1;
+    let id = table.next_id;
+    table.sockets.insert(
+        id,
+        WebSocketConn {
+            incoming: Rc::new(tokio::sync::Mutex::new(channels.incoming)),
+            outgoing: channels.outgoing,
+        },
+    );
+    return id;
+}
+
+pub async fn ws_handler(
+    State(state): State<RouteState>,
+    match_path: MatchedPath,
+    raw_params: RawPathParams,
+    uri: Uri,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response<Body> {
+    let parvals =
+        serde_json::Map::from_iter(raw_params.iter().map(|(k, v)| (String::from(k), v.into())));
+    let req = RouteRequest {
+        route_name: route_key("GET", match_path.as_str()),
+        route_args: parvals,
+        method: String::from("GET"),
+        path: String::from(uri.path()),
+        query: query_to_json(uri.query()),
+        headers: headers_to_json(&headers),
+        request_id: request_id(&headers),
+        ..Default::default()
+    };
+    ws.on_upgrade(move |socket| async move {
+        serve_websocket(state, req, socket).await;
+    })
+}
+
+async fn serve_websocket(state: RouteState, req: RouteRequest, mut socket: WebSocket) {
+    let (in_tx, in_rx) = mpsc::channel(32);
+    let (out_tx, mut out_rx) = mpsc::channel(32);
+    let sendres = state
+        .workers
+        .sender()
+        .send(RouteRequest {
+            websocket: Some(WebSocketChannels {
+                incoming: in_rx,
+                outgoing: out_tx,
+            }),
+            ..req
+        })
+        .await;
+    if let Err(e) = sendres {
+        tracing::error!("the JS worker stopped: {}", e);
+        return;
+    }
+
+    let mut ping = interval(PING_INTERVAL);
+    loop {
+        tokio::select! {
+            msg = socket.recv() => {
+                let msg = match msg {
+                    Some(Ok(Message::Text(text))) => WsMessage::Text(text),
+                    Some(Ok(Message::Binary(data))) => WsMessage::Binary(data),
+                    // pings are answered by axum, pongs only prove liveness
+                    Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+                    Some(Ok(Message::Close(frame))) => {
+                        let (code, reason) = frame
+                            .map(|f| (f.code, f.reason.into_owned()))
+                            .unwrap_or((1005, String::new()));
+                        let _ = in_tx.send(WsMessage::Close(code, reason)).await;
+                        return;
+                    }
+                    Some(Err(_)) | None => {
+                        let _ = in_tx.send(WsMessage::Close(1006, String::new())).await;
+                        return;
+                    }
+                };
+                if in_tx.send(msg).await.is_err() {
+                    return;
+                }
+            }
+            msg = out_rx.recv() => {
+                let msg = match msg {
+                    Some(WsMessage::Text(text)) => Message::Text(text),
+                    Some(WsMessage::Binary(data)) => Message::Binary(data),
+                    Some(WsMessage::Close(code, reason)) => {
+                        let frame = CloseFrame {
+                            code,
+                            reason: reason.clone().into(),
+                        };
+                        if socket.send(Message::Close(Some(frame))).await.is_ok() {
+                            // finish the closing handshake, so the peer sees
+                            // the code instead of a dropped connection
+                            let _ = timeout(CLOSE_TIMEOUT, async {
+                                while let Some(Ok(msg)) = socket.recv().await {
+                                    if let Message::Close(_) = msg {
+                                        break;
+                                    }
+                                }
+                            })
+                            .await;
+                        }
+                        // `onclose` gets the code JS closed with
+                        let _ = in_tx.send(WsMessage::Close(code, reason)).await;
+                        return;
+                    }
+                    None => return,
+                };
+                if socket.send(msg).await.is_err() {
+                    let _ = in_tx.send(WsMessage::Close(1006, String::new())).await;
+                    return;
+                }
+            }
+            _ = ping.tick() => {
+                if socket.send(Message::Ping(vec![])).await.is_err() {
+                    let _ = in_tx.send(WsMessage::Close(1006, String::new())).await;
+                    return;
+                }
+            }
+        }
+    }
+}
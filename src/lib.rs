@@ -0,0 +1,21 @@
+mod app;
+mod body;
+mod config;
+mod extensions;
+mod logging;
+mod metrics;
+mod module_loader;
+mod openapi;
+mod plugin;
+mod routing;
+mod runner;
+mod sqltojson;
+mod static_files;
+mod telemetry;
+mod validation;
+
+pub use app::{ScriptApp, ScriptAppBuilder};
+pub use logging::{init_logging, init_logging_with_writer, shutdown_logging};
+pub use plugin::Plugin;
+// plugins have to build their extensions with the same deno_core
+pub use deno_core;
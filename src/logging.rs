@@ -0,0 +1,169 @@
+use crate::metrics::worker_label;
+use crate::telemetry::{extract_context, init_tracer};
+use axum::extract::Request;
+use axum::http::HeaderMap;
+use axum::Router;
+use deno_core::error::AnyError;
+use deno_core::{op2, OpState};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::rc::Rc;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::{Level, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+// Installs a global subscriber for binaries embedding the app. `RUST_LOG`
+// sets the filter (default "info"), `LOG_FORMAT=json` prints one JSON
+// object per line, and spans are exported as described in `init_tracer`.
+// Has to be called inside a tokio runtime, and `shutdown_logging` before the
+// process exits.
+pub fn init_logging() -> Result<(), AnyError> {
+    // stderr, so `axum_script openapi` output stays parseable
+    return init_logging_with_writer(std::io::stderr);
+}
+
+// `init_logging` with the log lines written to `writer`
+pub fn init_logging_with_writer<W>(writer: W) -> Result<(), AnyError>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt = tracing_subscriber::fmt::layer().with_writer(writer);
+    let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> = vec![];
+    if env::var("LOG_FORMAT").as_deref() == Ok("json") {
+        layers.push(fmt.json().flatten_event(true).boxed());
+    } else {
+        layers.push(fmt.boxed());
+    }
+    if let Some(tracer) = init_tracer()? {
+        // the console span lives as long as its runtime and traces nothing
+        let not_console = filter_fn(|meta| meta.name() != CONSOLE_SPAN);
+        let otel = tracing_opentelemetry::layer().with_tracer(tracer);
+        layers.push(otel.with_filter(not_console).boxed());
+    }
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .try_init()?;
+    return Ok(());
+}
+
+pub fn request_id(headers: &HeaderMap) -> String {
+    let id = headers.get("x-request-id").and_then(|id| id.to_str().ok());
+    return String::from(id.unwrap_or(""));
+}
+
+// Exports the spans still buffered. Blocks until the exporters are done, so
+// call it from a blocking thread when inside an async runtime.
+pub fn shutdown_logging() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+// The span of a JS handler, `parent` is the dispatching span on the HTTP
+// side. See `RequestSpans` for how ops find it.
+pub fn handler_span(parent: Option<&Span>, request_id: &str, route_name: &str) -> Span {
+    let (method, route) = route_name.split_once(' ').unwrap_or(("", route_name));
+    return tracing::info_span!(
+        parent: parent.and_then(|parent| parent.id()),
+        "handler",
+        request_id,
+        method,
+        route
+    );
+}
+
+// The handler spans of the requests a runtime is serving, by the `context`
+// id their JS request object carries. Handlers share one event loop, so the
+// current span of an op is that of whichever request happens to poll it; JS
+// passes the id of the request it works for instead.
+pub struct RequestSpans {
+    next_id: u32,
+    spans: HashMap<u32, Span>,
+    // the parent of console output outside of requests
+    console: Span,
+}
+
+const CONSOLE_SPAN: &str = "console";
+
+impl RequestSpans {
+    pub fn new() -> RequestSpans {
+        return RequestSpans {
+            next_id: 0,
+            spans: HashMap::new(),
+            console: tracing::info_span!(parent: None, CONSOLE_SPAN, worker = %worker_label()),
+        };
+    }
+
+    pub fn insert(&mut self, span: Span) -> u32 {
+        // 0 stands for "no request"
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.spans.insert(self.next_id, span);
+        return self.next_id;
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.spans.remove(&id);
+    }
+}
+
+// the span of request `context`, the parent for spans of the ops JS runs on
+// its behalf; a disabled one outside of requests, so those spans start a
+// trace of their own
+pub fn request_span(state: &OpState, context: u32) -> Span {
+    let spans = state.borrow::<Rc<RefCell<RequestSpans>>>().borrow();
+    return spans
+        .spans
+        .get(&context)
+        .cloned()
+        .unwrap_or_else(Span::none);
+}
+
+// Every request gets an `x-request-id` (kept when the client sent one) that
+// is echoed on the response, and an access log line once it completes.
+pub fn with_access_log(app: Router) -> Router {
+    return app
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|req: &Request| {
+                    let span = tracing::info_span!(
+                        "request",
+                        otel.kind = "server",
+                        method = %req.method(),
+                        uri = %req.uri(),
+                        request_id = request_id(req.headers()),
+                    );
+                    // continues the caller's trace from `traceparent`
+                    span.set_parent(extract_context(req.headers()));
+                    span
+                })
+                .on_request(())
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid));
+}
+
+// console.debug/log/info/warn/error with the context of the request whose
+// code is running, 0 outside of requests, and `req.log.*` with that of its
+// request
+#[op2(fast)]
+pub fn op_log(state: &mut OpState, #[string] level: &str, #[string] message: &str, context: u32) {
+    let parent = {
+        let spans = state.borrow::<Rc<RefCell<RequestSpans>>>().borrow();
+        let span = spans.spans.get(&context).unwrap_or(&spans.console);
+        span.id()
+    };
+    match level {
+        "debug" => tracing::debug!(target: "js", parent: parent, "{}", message),
+        "warn" => tracing::warn!(target: "js", parent: parent, "{}", message),
+        "error" => tracing::error!(target: "js", parent: parent, "{}", message),
+        _ => tracing::info!(target: "js", parent: parent, "{}", message),
+    }
+}
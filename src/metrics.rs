@@ -0,0 +1,213 @@
+use crate::routing::WorkerPool;
+use axum::extract::{MatchedPath, Request};
+use axum::http::header::CONTENT_TYPE;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Any, Pool};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use tokio::time::Instant;
+
+// `metrics: { path: "/metrics" }`, served only with a path
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub path: Option<String>,
+}
+
+// One registry for the process, shared by every app built in it, so two apps
+// add up under the same worker labels; the JS workers add their own metrics
+// to it with `metrics.counter()` and `metrics.histogram()`.
+pub struct Metrics {
+    pub registry: Registry,
+    pub requests: IntCounterVec,
+    pub request_duration: HistogramVec,
+    pub queue_depth: IntGaugeVec,
+    pub in_flight: IntGaugeVec,
+    pub heap_used: IntGaugeVec,
+    pub heap_total: IntGaugeVec,
+    pub db_connections: IntGaugeVec,
+    pub db_query_duration: HistogramVec,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+    registry.register(Box::new(counter.clone())).unwrap();
+    return counter;
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    let gauge = IntGaugeVec::new(Opts::new(name, help), labels).unwrap();
+    registry.register(Box::new(gauge.clone())).unwrap();
+    return gauge;
+}
+
+fn histogram(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> HistogramVec {
+    let histogram = HistogramVec::new(HistogramOpts::new(name, help), labels).unwrap();
+    registry.register(Box::new(histogram.clone())).unwrap();
+    return histogram;
+}
+
+pub fn metrics() -> &'static Metrics {
+    return METRICS.get_or_init(|| {
+        let registry = Registry::new();
+        Metrics {
+            requests: counter(
+                &registry,
+                "http_requests_total",
+                "HTTP requests by route, method and status",
+                &["route", "method", "status"],
+            ),
+            request_duration: histogram(
+                &registry,
+                "http_request_duration_seconds",
+                "HTTP request latency by route and method",
+                &["route", "method"],
+            ),
+            queue_depth: gauge(
+                &registry,
+                "js_worker_queue_depth",
+                "Requests waiting in a JS worker's channel",
+                &["worker"],
+            ),
+            in_flight: gauge(
+                &registry,
+                "js_worker_requests_in_flight",
+                "Requests a JS worker has started and not answered yet",
+                &["worker"],
+            ),
+            heap_used: gauge(
+                &registry,
+                "v8_heap_used_bytes",
+                "Used V8 heap of a JS worker's isolate",
+                &["worker"],
+            ),
+            heap_total: gauge(
+                &registry,
+                "v8_heap_total_bytes",
+                "Total V8 heap of a JS worker's isolate",
+                &["worker"],
+            ),
+            db_connections: gauge(
+                &registry,
+                "db_pool_connections",
+                "Database pool connections of a JS worker by state",
+                &["worker", "state"],
+            ),
+            db_query_duration: histogram(
+                &registry,
+                "db_query_duration_seconds",
+                "Duration of query() and execute() calls",
+                &["op"],
+            ),
+            registry,
+        }
+    });
+}
+
+// the label for per-worker metrics, see `JsRunner::spawn_thread`
+pub fn worker_label() -> String {
+    return String::from(thread::current().name().unwrap_or("main"));
+}
+
+// The database pools of the runtimes, by worker, so their connection counts
+// are current whenever the metrics are read.
+static DB_POOLS: OnceLock<Mutex<HashMap<String, Pool<Any>>>> = OnceLock::new();
+
+fn db_pools() -> &'static Mutex<HashMap<String, Pool<Any>>> {
+    return DB_POOLS.get_or_init(|| Mutex::new(HashMap::new()));
+}
+
+pub fn record_db_connections(worker: &str, pool: &Pool<Any>) {
+    let metrics = metrics();
+    let idle = pool.num_idle() as i64;
+    metrics
+        .db_connections
+        .with_label_values(&[worker, "idle"])
+        .set(idle);
+    metrics
+        .db_connections
+        .with_label_values(&[worker, "active"])
+        .set(pool.size() as i64 - idle);
+}
+
+// Kept in the state of a runtime connected to a database. Dropped with the
+// runtime, it forgets the pool again, so the pool can close and its gauges
+// don't linger.
+pub struct DbPoolMetrics {
+    worker: String,
+}
+
+impl DbPoolMetrics {
+    pub fn register(pool: &Pool<Any>) -> DbPoolMetrics {
+        let worker = worker_label();
+        record_db_connections(&worker, pool);
+        db_pools()
+            .lock()
+            .unwrap()
+            .insert(worker.clone(), pool.clone());
+        return DbPoolMetrics { worker };
+    }
+}
+
+impl Drop for DbPoolMetrics {
+    fn drop(&mut self) {
+        db_pools().lock().unwrap().remove(&self.worker);
+        for state in ["idle", "active"] {
+            let _ = metrics()
+                .db_connections
+                .remove_label_values(&[&self.worker, state]);
+        }
+    }
+}
+
+// Counts requests by their matched route pattern rather than the URI, so
+// the number of series stays bounded. Requests no route matched, answered
+// by the `notFound()` handler or a plain 404, have an empty route.
+pub async fn track_requests(req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| String::from(path.as_str()))
+        .unwrap_or_default();
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let resp = next.run(req).await;
+    let metrics = metrics();
+    metrics
+        .request_duration
+        .with_label_values(&[&route, &method])
+        .observe(started.elapsed().as_secs_f64());
+    metrics
+        .requests
+        .with_label_values(&[&route, &method, resp.status().as_str()])
+        .inc();
+    return resp;
+}
+
+pub fn metrics_response(workers: &Arc<WorkerPool>) -> Response {
+    let metrics = metrics();
+    for (i, depth) in workers.queue_depths().into_iter().enumerate() {
+        let worker = format!("js-worker-{}", i);
+        metrics
+            .queue_depth
+            .with_label_values(&[&worker])
+            .set(depth as i64);
+    }
+    for (worker, pool) in db_pools().lock().unwrap().iter() {
+        record_db_connections(worker, pool);
+    }
+    let encoder = TextEncoder::new();
+    let mut body = vec![];
+    encoder
+        .encode(&metrics.registry.gather(), &mut body)
+        .unwrap();
+    return ([(CONTENT_TYPE, String::from(encoder.format_type()))], body).into_response();
+}
@@ -0,0 +1,308 @@
+use deno_ast::{
+    EmitOptions, MediaType, ParseParams, SourceMapOption, SourceTextInfo, TranspileOptions,
+};
+use deno_core::anyhow::{anyhow, bail};
+use deno_core::error::AnyError;
+use deno_core::url::Url;
+use deno_core::{
+    resolve_import, ModuleLoadResponse, ModuleLoader, ModuleSource, ModuleSourceCode,
+    ModuleSpecifier, ModuleType, RequestedModuleType, ResolutionKind,
+};
+use import_map::ImportMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// bump when the cached entry format changes
+const TRANSPILE_CACHE_VERSION: u32 = 2;
+
+// workers transpile the same modules at the same time
+static NEXT_CACHE_WRITE: AtomicU64 = AtomicU64::new(0);
+
+// Loads modules from disk like `FsModuleLoader`, but type-strips `.ts`,
+// `.tsx` and `.jsx` files on the way. No type checking is done.
+pub struct ScriptModuleLoader {
+    // bare specifiers like "lodash" or "utils/" mapped to files
+    import_map: Option<ImportMap>,
+    // generated source maps by module URL, so stack traces point to the
+    // original lines
+    source_maps: RefCell<HashMap<String, Vec<u8>>>,
+    // private directory of transpiled modules, None when it can't be created
+    transpile_cache: Option<PathBuf>,
+}
+
+// `path` in the import map format:
+// `{ "imports": { "utils/": "./vendor/utils/" }, "scopes": { ... } }`,
+// with the `imports` and `scopes` of the config file added to (and
+// overriding) its own
+fn load_import_map(
+    path: &Path,
+    config_file: &Map<String, Value>,
+) -> Result<Option<ImportMap>, AnyError> {
+    let configured = ["imports", "scopes"]
+        .into_iter()
+        .filter_map(|key| Some((key, config_file.get(key)?.as_object()?)))
+        .collect::<Vec<_>>();
+    let mut json = match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str::<Value>(&json)
+            .map_err(|e| anyhow!("invalid import map {}: {}", path.display(), e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !configured.is_empty() => json!({}),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => bail!("cannot read {}: {}", path.display(), e),
+    };
+    if let Some(map) = json.as_object_mut() {
+        for (key, entries) in configured {
+            if let Some(own) = map.entry(key).or_insert_with(|| json!({})).as_object_mut() {
+                own.extend(entries.clone());
+            }
+        }
+    }
+    let base_url = Url::from_file_path(path)
+        .map_err(|_| anyhow!("invalid import map path {}", path.display()))?;
+    let parsed = import_map::parse_from_json(&base_url, &json.to_string())
+        .map_err(|e| anyhow!("invalid import map {}: {}", path.display(), e))?;
+    for diagnostic in parsed.diagnostics {
+        tracing::warn!("{}: {}", path.display(), diagnostic);
+    }
+    return Ok(Some(parsed.import_map));
+}
+
+fn transpile_options() -> (TranspileOptions, EmitOptions) {
+    let emit = EmitOptions {
+        source_map: SourceMapOption::Separate,
+        inline_sources: true,
+        ..Default::default()
+    };
+    return (TranspileOptions::default(), emit);
+}
+
+// A per-user directory (`$XDG_CACHE_HOME` or `~/.cache`) unless one is given.
+// Only its owner can read or write it, so other users can't plant code that
+// would be loaded as a module. Without one the cache is skipped.
+fn transpile_cache_dir(dir: Option<&Path>) -> Option<PathBuf> {
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => {
+            let base = match env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+                Some(dir) => PathBuf::from(dir),
+                None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+            };
+            base.join("axum_script").join("transpile")
+        }
+    };
+    if let Err(e) = create_private_dir(&dir) {
+        tracing::warn!("not caching transpiled modules in {}: {}", dir.display(), e);
+        return None;
+    }
+    return Some(dir);
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    // an existing directory may have been created with wider permissions
+    return fs::set_permissions(dir, fs::Permissions::from_mode(0o700));
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    return fs::create_dir_all(dir);
+}
+
+fn sha256_hex(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        // length-prefixed, so different splits can't hash the same
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    return format!("{:x}", hasher.finalize());
+}
+
+// names the entry after everything the output depends on
+fn transpile_cache_key(specifier: &ModuleSpecifier, code: &str) -> String {
+    let (transpile_options, emit_options) = transpile_options();
+    let options = format!("{:?} {:?}", transpile_options, emit_options);
+    let version = TRANSPILE_CACHE_VERSION.to_string();
+    return sha256_hex(&[&version, specifier.as_str(), &options, code]);
+}
+
+#[derive(Deserialize, Serialize)]
+struct CachedTranspile {
+    key: String,
+    // of `code` and `map`, checked before an entry is used
+    hash: String,
+    code: String,
+    map: String,
+}
+
+fn read_cached_transpile(path: &Path, key: &str) -> Option<(String, String)> {
+    let json = fs::read_to_string(path).ok()?;
+    let cached = serde_json::from_str::<CachedTranspile>(&json).ok()?;
+    if cached.key != key || cached.hash != sha256_hex(&[&cached.code, &cached.map]) {
+        tracing::warn!("ignoring corrupt transpile cache entry {}", path.display());
+        return None;
+    }
+    return Some((cached.code, cached.map));
+}
+
+// written to a temp file and renamed, so readers never see half an entry
+fn write_cached_transpile(path: &Path, key: &str, code: &str, map: &str) -> std::io::Result<()> {
+    let cached = CachedTranspile {
+        key: String::from(key),
+        hash: sha256_hex(&[code, map]),
+        code: String::from(code),
+        map: String::from(map),
+    };
+    let id = NEXT_CACHE_WRITE.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_extension(format!("tmp-{}-{}", std::process::id(), id));
+    fs::write(&tmp, serde_json::to_vec(&cached)?)?;
+    return fs::rename(&tmp, path);
+}
+
+// (code, source map), read from the cache when the file did not change
+fn transpile(
+    cache_dir: Option<&Path>,
+    specifier: &ModuleSpecifier,
+    media_type: MediaType,
+    code: String,
+) -> Result<(String, String), AnyError> {
+    let key = transpile_cache_key(specifier, &code);
+    let cached = cache_dir.map(|dir| dir.join(format!("{}.json", key)));
+    if let Some(cached) = cached
+        .as_ref()
+        .and_then(|path| read_cached_transpile(path, &key))
+    {
+        return Ok(cached);
+    }
+
+    let parsed = deno_ast::parse_module(ParseParams {
+        specifier: specifier.clone(),
+        text_info: SourceTextInfo::from_string(code),
+        media_type,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })?;
+    let (transpile_options, emit_options) = transpile_options();
+    let emitted = parsed
+        .transpile(&transpile_options, &emit_options)?
+        .into_source();
+    let source_map = emitted
+        .source_map
+        .ok_or_else(|| anyhow!("no source map generated for {}", specifier))?;
+
+    // a failed write only costs a transpile on the next start
+    if let Some(path) = cached {
+        let _ = write_cached_transpile(&path, &key, &emitted.text, &source_map);
+    }
+    return Ok((emitted.text, source_map));
+}
+
+impl ScriptModuleLoader {
+    // `config_file` is the app's `axum_script.json`, read before the entry
+    // so its import map also applies to the entry's static imports
+    pub fn new(
+        entry_file: &Path,
+        config_file: &Map<String, Value>,
+        transpile_cache: Option<&Path>,
+    ) -> Result<ScriptModuleLoader, AnyError> {
+        let import_map_path = entry_file.with_file_name("imports.json");
+        let import_map = load_import_map(&import_map_path, config_file)?;
+        return Ok(ScriptModuleLoader {
+            import_map,
+            source_maps: RefCell::default(),
+            transpile_cache: transpile_cache_dir(transpile_cache),
+        });
+    }
+
+    fn load_file(
+        &self,
+        specifier: &ModuleSpecifier,
+        requested_module_type: RequestedModuleType,
+    ) -> Result<ModuleSource, AnyError> {
+        let path = specifier
+            .to_file_path()
+            .map_err(|_| anyhow!("only file:// modules can be loaded, got {}", specifier))?;
+        let media_type = MediaType::from_path(&path);
+        // JSON has to be asked for with `import data from "./x.json" with { type: "json" }`
+        match (&requested_module_type, media_type) {
+            (RequestedModuleType::Json, MediaType::Json) => {}
+            (RequestedModuleType::Json, _) => bail!("{} is not a JSON module", specifier),
+            (_, MediaType::Json) => bail!(
+                "{} is a JSON module, import it with `with {{ type: \"json\" }}`",
+                specifier
+            ),
+            _ => {}
+        }
+        let (module_type, should_transpile) = match media_type {
+            MediaType::JavaScript | MediaType::Mjs | MediaType::Cjs => {
+                (ModuleType::JavaScript, false)
+            }
+            MediaType::Jsx
+            | MediaType::TypeScript
+            | MediaType::Mts
+            | MediaType::Cts
+            | MediaType::Tsx => (ModuleType::JavaScript, true),
+            MediaType::Json => (ModuleType::Json, false),
+            _ => bail!("cannot load {}: unknown file type", path.display()),
+        };
+        let code = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("cannot load {}: {}", path.display(), e))?;
+        let code = if should_transpile {
+            let cache_dir = self.transpile_cache.as_deref();
+            let (code, source_map) = transpile(cache_dir, specifier, media_type, code)?;
+            self.source_maps
+                .borrow_mut()
+                .insert(specifier.to_string(), source_map.into_bytes());
+            code
+        } else {
+            code
+        };
+        return Ok(ModuleSource::new(
+            module_type,
+            ModuleSourceCode::String(code.into()),
+            specifier,
+            None,
+        ));
+    }
+}
+
+impl ModuleLoader for ScriptModuleLoader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, AnyError> {
+        // the entry file is resolved against a non-URL referrer
+        if let (Some(import_map), Ok(referrer)) = (&self.import_map, Url::parse(referrer)) {
+            return Ok(import_map.resolve(specifier, &referrer)?);
+        }
+        return Ok(resolve_import(specifier, referrer)?);
+    }
+
+    fn load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        _maybe_referrer: Option<&ModuleSpecifier>,
+        _is_dyn_import: bool,
+        requested_module_type: RequestedModuleType,
+    ) -> ModuleLoadResponse {
+        return ModuleLoadResponse::Sync(self.load_file(module_specifier, requested_module_type));
+    }
+
+    fn get_source_map(&self, specifier: &str) -> Option<Vec<u8>> {
+        return self.source_maps.borrow().get(specifier).cloned();
+    }
+}
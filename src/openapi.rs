@@ -0,0 +1,113 @@
+use crate::config::OpenApiConfig;
+use crate::routing::{split_route_key, RouteOptions};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+
+// "/pets/:id/*rest" becomes "/pets/{id}/{rest}"
+fn openapi_path(path: &str) -> (String, Vec<String>) {
+    let mut params = vec![];
+    let segments = path.split('/').map(|segment| {
+        match segment
+            .strip_prefix(':')
+            .or_else(|| segment.strip_prefix('*'))
+        {
+            Some(name) => {
+                params.push(String::from(name));
+                format!("{{{}}}", name)
+            }
+            None => String::from(segment),
+        }
+    });
+    let path = segments.collect::<Vec<_>>().join("/");
+    return (path, params);
+}
+
+fn property_schema(schema: Option<&Value>, name: &str) -> Value {
+    let property = schema.and_then(|schema| schema.get("properties")?.get(name));
+    return property.cloned().unwrap_or(json!({ "type": "string" }));
+}
+
+fn is_required(schema: Option<&Value>, name: &str) -> bool {
+    let required = schema.and_then(|schema| schema.get("required")?.as_array());
+    return required.is_some_and(|required| required.iter().any(|r| r == name));
+}
+
+fn operation(options: &RouteOptions, path_params: &[String]) -> Value {
+    let schema = options.schema.clone().unwrap_or_default();
+    let mut parameters = vec![];
+    for name in path_params {
+        parameters.push(json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "schema": property_schema(schema.params.as_ref(), name),
+        }));
+    }
+    let query_properties = schema
+        .query
+        .as_ref()
+        .and_then(|query| query.get("properties")?.as_object());
+    for name in query_properties.into_iter().flat_map(|props| props.keys()) {
+        parameters.push(json!({
+            "name": name,
+            "in": "query",
+            "required": is_required(schema.query.as_ref(), name),
+            "schema": property_schema(schema.query.as_ref(), name),
+        }));
+    }
+
+    let mut op = serde_json::Map::new();
+    if let Some(summary) = &options.summary {
+        op.insert(String::from("summary"), json!(summary));
+    }
+    if let Some(description) = &options.description {
+        op.insert(String::from("description"), json!(description));
+    }
+    if !options.tags.is_empty() {
+        op.insert(String::from("tags"), json!(options.tags));
+    }
+    if !parameters.is_empty() {
+        op.insert(String::from("parameters"), Value::Array(parameters));
+    }
+    if let Some(body) = &schema.body {
+        op.insert(
+            String::from("requestBody"),
+            json!({
+                "required": true,
+                "content": { "application/json": { "schema": body } },
+            }),
+        );
+    }
+    let ok = match &schema.response {
+        Some(response) => json!({
+            "description": "OK",
+            "content": { "application/json": { "schema": response } },
+        }),
+        None => json!({ "description": "OK" }),
+    };
+    op.insert(String::from("responses"), json!({ "200": ok }));
+    return Value::Object(op);
+}
+
+// OpenAPI 3.1 document for the HTTP routes; websocket routes are left out
+pub fn openapi_document(routes: &HashMap<String, RouteOptions>, config: &OpenApiConfig) -> Value {
+    let mut paths: BTreeMap<String, serde_json::Map<String, Value>> = BTreeMap::new();
+    for (key, options) in routes {
+        let Some((method, path)) = split_route_key(key) else {
+            continue;
+        };
+        if options.websocket {
+            continue;
+        }
+        let (path, params) = openapi_path(path);
+        paths
+            .entry(path)
+            .or_default()
+            .insert(method.to_lowercase(), operation(options, &params));
+    }
+    return json!({
+        "openapi": "3.1.0",
+        "info": { "title": config.title, "version": config.version },
+        "paths": paths,
+    });
+}
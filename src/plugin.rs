@@ -0,0 +1,43 @@
+use deno_core::{Extension, OpState};
+
+// A Rust extension of the JS runtime, added with `ScriptAppBuilder::plugin`.
+// Every runtime (each worker and the one that discovers the routes) gets its
+// own `Extension` and its own state, so a plugin is shared between threads
+// and only has to hand out fresh copies.
+//
+//     deno_core::extension!(
+//         geo_extension,
+//         ops = [op_distance],
+//         js = ["src/geo.js"],
+//         state = |state| state.put(GeoIndex::default())
+//     );
+//
+//     struct Geo;
+//
+//     impl Plugin for Geo {
+//         fn extension(&self) -> Extension {
+//             return geo_extension::init_ops_and_esm();
+//         }
+//     }
+//
+// The extension's `js` files are run as classic scripts after the built-in
+// globals are set up and before the entry file is loaded. ES modules are not
+// supported.
+pub trait Plugin: Send + Sync + 'static {
+    fn extension(&self) -> Extension;
+
+    // Runs on each runtime's state after the extensions are set up, for state
+    // that depends on the plugin's own configuration (connection strings,
+    // shared handles, ...) rather than on the extension alone.
+    fn init_state(&self, _state: &mut OpState) {}
+}
+
+// `.extension(my_extension::init_ops_and_esm)`
+impl<F> Plugin for F
+where
+    F: Fn() -> Extension + Send + Sync + 'static,
+{
+    fn extension(&self) -> Extension {
+        return self();
+    }
+}
@@ -1,17 +1,204 @@
+use crate::config::AppConfig;
+use crate::extensions::datacache::DataCache;
+use crate::extensions::websocket::WebSocketChannels;
+use crate::validation::{RequestValidator, RouteSchema};
 use axum::body::Body;
-use axum::response::Response;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use deno_core::anyhow::bail;
+use deno_core::error::AnyError;
+use serde::Deserialize;
 use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 
+#[derive(Default)]
 pub struct RouteRequest {
     pub route_name: String,
     pub response_channel: Option<oneshot::Sender<Response<Body>>>,
     pub route_args: serde_json::Map<String, Value>,
+    pub method: String,
+    pub path: String,
+    pub query: serde_json::Map<String, Value>,
+    pub headers: serde_json::Map<String, Value>,
+    pub websocket: Option<WebSocketChannels>,
+    pub body: Option<Value>,
+    // resolves with true on timeout and false once the response was taken;
+    // the sender is dropped when the client disconnects
+    pub abort: Option<oneshot::Receiver<bool>>,
+    // from the `x-request-id` header, see `logging::with_access_log`
+    pub request_id: String,
+    // the span of the HTTP side, see `dispatch`
+    pub span: Option<tracing::Span>,
+    // the handler span's id in `RequestSpans`, set by the worker
+    pub context: u32,
     //request: Request,
 }
 
+pub enum RouteError {
+    NotFound,
+    Failed(AnyError),
+}
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> Response {
+        match self {
+            RouteError::NotFound => (StatusCode::NOT_FOUND, Html("404 not found")).into_response(),
+            RouteError::Failed(e) => {
+                tracing::error!("{:#}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, Html("Error")).into_response()
+            }
+        }
+    }
+}
+
+// options passed as the optional second argument of `route(path, options, handler)`
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RouteOptions {
+    pub cache_etag: bool,
+    pub websocket: bool,
+    // defaults to GET
+    pub methods: Vec<String>,
+    // overrides `bodyLimit` from `configure()`
+    pub body_limit: Option<usize>,
+    pub schema: Option<RouteSchema>,
+    // only used for the OpenAPI document
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    // milliseconds, overrides `timeout` from `configure()`
+    pub timeout: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct RouteEntry {
+    pub handler: v8::Global<v8::Function>,
+    pub options: RouteOptions,
+}
+
+// the request channels of the JS worker threads
+pub struct WorkerPool {
+    senders: Vec<mpsc::Sender<RouteRequest>>,
+    next: AtomicUsize,
+}
+
+impl WorkerPool {
+    pub fn new(senders: Vec<mpsc::Sender<RouteRequest>>) -> WorkerPool {
+        return WorkerPool {
+            senders,
+            next: AtomicUsize::new(0),
+        };
+    }
+
+    // round-robin, a handler waiting on I/O does not hold up other workers
+    pub fn sender(&self) -> &mpsc::Sender<RouteRequest> {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.senders.len();
+        return &self.senders[i];
+    }
+
+    // requests sent to each worker that it has not picked up yet
+    pub fn queue_depths(&self) -> Vec<usize> {
+        return self
+            .senders
+            .iter()
+            .map(|sender| sender.max_capacity() - sender.capacity())
+            .collect();
+    }
+}
+
 #[derive(Clone)]
 pub struct RouteState {
-    pub tx_req: mpsc::Sender<RouteRequest>,
+    pub workers: Arc<WorkerPool>,
+    pub routes: Arc<HashMap<String, RouteOptions>>,
+    pub config: Arc<AppConfig>,
+    pub validators: Arc<HashMap<String, RequestValidator>>,
+    pub cache: Arc<DataCache>,
+}
+
+// HTTP routes are keyed by method and path, e.g. "POST /users/:id"; the
+// built-in handlers such as "__not_found" keep their plain name
+pub fn route_key(method: &str, path: &str) -> String {
+    return format!("{} {}", method, path);
+}
+
+pub fn split_route_key(key: &str) -> Option<(&str, &str)> {
+    return key.split_once(' ');
+}
+
+// header names are lowercase, repeated headers are joined with ", "
+pub fn headers_to_json(headers: &HeaderMap) -> serde_json::Map<String, Value> {
+    let mut res = serde_json::Map::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        match res.get_mut(name.as_str()) {
+            Some(Value::String(prev)) => {
+                prev.push_str(", ");
+                prev.push_str(&value);
+            }
+            _ => {
+                res.insert(String::from(name.as_str()), Value::String(value));
+            }
+        }
+    }
+    return res;
+}
+
+pub fn query_to_json(query: Option<&str>) -> serde_json::Map<String, Value> {
+    let pairs = deno_core::url::form_urlencoded::parse(query.unwrap_or("").as_bytes());
+    return serde_json::Map::from_iter(
+        pairs.map(|(k, v)| (k.into_owned(), Value::String(v.into_owned()))),
+    );
+}
+
+// "/posts/:id?" registers both "/posts" and "/posts/:id"
+pub fn expand_optional_segments(path: &str) -> Result<Vec<String>, AnyError> {
+    if !path.starts_with('/') {
+        return Ok(vec![String::from(path)]);
+    }
+    let mut variants = vec![];
+    let mut current = String::new();
+    let mut seen_optional = false;
+    for segment in path.split('/').skip(1) {
+        match segment.strip_suffix('?') {
+            Some(segment) => {
+                seen_optional = true;
+                variants.push(if current.is_empty() {
+                    String::from("/")
+                } else {
+                    current.clone()
+                });
+                current.push('/');
+                current.push_str(segment);
+            }
+            None => {
+                if seen_optional {
+                    bail!("optional segments must come last in route {}", path);
+                }
+                current.push('/');
+                current.push_str(segment);
+            }
+        }
+    }
+    variants.push(current);
+    return Ok(variants);
+}
+
+// Runs the paths through the same matcher axum uses, so that overlapping
+// routes are reported at startup instead of panicking inside the router.
+pub fn check_route_conflicts<'a>(paths: impl Iterator<Item = &'a str>) -> Result<(), AnyError> {
+    let mut paths = paths
+        .filter(|path| path.starts_with('/'))
+        .collect::<Vec<_>>();
+    paths.sort();
+    let mut matcher = matchit::Router::new();
+    for path in paths {
+        if let Err(e) = matcher.insert(path, ()) {
+            bail!("route {} cannot be registered: {}", path, e);
+        }
+    }
+    return Ok(());
 }
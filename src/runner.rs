@@ -0,0 +1,842 @@
+use crate::body::{is_upload_path, STREAM_CHUNKS};
+use crate::config::{read_config_file, AppConfig, CONFIG_FILE};
+use crate::extensions::database::database_extension;
+use crate::extensions::datacache::{datacache_extension, DataCache};
+use crate::extensions::fetch::fetch_extension;
+use crate::extensions::metrics::metrics_extension;
+use crate::extensions::timers::timers_extension;
+use crate::extensions::websocket::{register_websocket, websocket_extension};
+use crate::logging::{handler_span, op_log, RequestSpans};
+use crate::metrics::{metrics, worker_label};
+use crate::module_loader::ScriptModuleLoader;
+use crate::plugin::Plugin;
+use crate::routing::{
+    expand_optional_segments, route_key, split_route_key, RouteEntry, RouteError, RouteOptions,
+    RouteRequest,
+};
+use crate::static_files::{StaticDir, StaticDirOptions};
+use crate::validation::{compile_schema, problem_response, validate};
+use axum::body::{Body, Bytes};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::Json;
+use deno_core::anyhow::bail;
+use deno_core::error::{AnyError, JsError};
+use deno_core::op2;
+use deno_core::serde_v8::from_v8;
+use deno_core::{serde_v8::to_v8, OpState};
+use deno_core::{Extension, JsBuffer, JsRuntime, ToJsBuffer};
+use jsonschema::JSONSchema;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::env;
+use std::future::poll_fn;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Poll;
+use std::thread;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::sync::Notify;
+use tokio::task;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::Instrument;
+
+#[op2()]
+fn op_route(
+    state: &mut OpState,
+    #[string] path: &str,
+    #[serde] options: RouteOptions,
+    #[global] router: v8::Global<v8::Function>,
+) -> Result<(), AnyError> {
+    let hmref = state.borrow::<Rc<RefCell<HashMap<String, RouteEntry>>>>();
+    let mut routes = hmref.borrow_mut();
+    let methods = if options.methods.is_empty() {
+        vec![String::from("GET")]
+    } else {
+        options.methods.iter().map(|m| m.to_uppercase()).collect()
+    };
+    for variant in expand_optional_segments(path)? {
+        let keys = if variant.starts_with('/') {
+            methods.iter().map(|m| route_key(m, &variant)).collect()
+        } else {
+            vec![variant]
+        };
+        for key in keys {
+            if routes.contains_key(&key) {
+                bail!("route {} is registered more than once", key);
+            }
+            routes.insert(
+                key,
+                RouteEntry {
+                    handler: router.clone(),
+                    options: options.clone(),
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+#[op2()]
+fn op_configure(
+    state: &mut OpState,
+    #[serde] values: serde_json::Map<String, Value>,
+) -> Result<(), AnyError> {
+    if values.contains_key("imports") {
+        // the entry's own imports are resolved before it can call configure()
+        bail!(
+            "imports can't be configured at runtime, set them in {}",
+            CONFIG_FILE
+        );
+    }
+    let configref = state.borrow::<Rc<RefCell<AppConfig>>>();
+    let merged = configref.borrow().merge(values)?;
+    *configref.borrow_mut() = merged;
+    Ok(())
+}
+
+// contents of a file part from `req.multipart()`
+#[op2(async)]
+#[serde]
+async fn op_read_upload(#[string] path: String) -> Result<ToJsBuffer, AnyError> {
+    let path = PathBuf::from(path);
+    if !is_upload_path(&path) {
+        bail!("{} is not an uploaded file", path.display());
+    }
+    let contents = tokio::fs::read(&path).await?;
+    Ok(ToJsBuffer::from(contents))
+}
+
+#[op2()]
+fn op_static_dir(
+    state: &mut OpState,
+    #[string] prefix: String,
+    #[string] dir: String,
+    #[serde] options: StaticDirOptions,
+) -> Result<(), AnyError> {
+    if !prefix.starts_with('/') {
+        bail!("staticDir prefix must start with \"/\", got \"{}\"", prefix);
+    }
+    // "/assets/" is mounted like "/assets", only the root keeps its slash
+    let prefix = match prefix.trim_end_matches('/') {
+        "" => String::from("/"),
+        trimmed => String::from(trimmed),
+    };
+    let dirsref = state.borrow::<Rc<RefCell<Vec<StaticDir>>>>();
+    dirsref.borrow_mut().push(StaticDir {
+        prefix,
+        dir,
+        options,
+    });
+    return Ok(());
+}
+
+// chunks of streamed responses, written by JS and read by the response body
+#[derive(Default)]
+struct ResponseStreams {
+    next_id: u32,
+    senders: HashMap<u32, mpsc::Sender<Bytes>>,
+    // keyed by stream id, along with the request context that created it
+    receivers: HashMap<u32, (u32, mpsc::Receiver<Bytes>)>,
+}
+
+#[op2(fast)]
+fn op_stream_create(state: &mut OpState, context: u32) -> u32 {
+    let streams = state.borrow::<Rc<RefCell<ResponseStreams>>>();
+    let mut streams = streams.borrow_mut();
+    let (tx, rx) = mpsc::channel(STREAM_CHUNKS);
+    streams.next_id += 1;
+    let id = streams.next_id;
+    streams.senders.insert(id, tx);
+    streams.receivers.insert(id, (context, rx));
+    return id;
+}
+
+// resolves to false once the client has gone away
+#[op2(async)]
+async fn op_stream_write(state: Rc<RefCell<OpState>>, id: u32, #[buffer] chunk: JsBuffer) -> bool {
+    let sender = {
+        let state = state.borrow();
+        let streams = state.borrow::<Rc<RefCell<ResponseStreams>>>().borrow();
+        streams.senders.get(&id).cloned()
+    };
+    match sender {
+        Some(sender) => sender.send(Bytes::from(chunk.to_vec())).await.is_ok(),
+        None => false,
+    }
+}
+
+#[op2(fast)]
+fn op_stream_close(state: &mut OpState, id: u32) {
+    let streams = state.borrow::<Rc<RefCell<ResponseStreams>>>();
+    // The receiver stays until `stream_response` takes it: an iterator that
+    // ends before the handler has returned still needs a response body.
+    streams.borrow_mut().senders.remove(&id);
+}
+
+// abort notifications of the requests currently in a handler
+#[derive(Default)]
+struct RequestAborts {
+    next_id: u32,
+    receivers: HashMap<u32, oneshot::Receiver<bool>>,
+}
+
+// resolves to "timeout", "disconnect" or "" when the request completed
+#[op2(async)]
+#[string]
+async fn op_wait_abort(state: Rc<RefCell<OpState>>, id: u32) -> String {
+    let receiver = {
+        let state = state.borrow();
+        let aborts = state.borrow::<Rc<RefCell<RequestAborts>>>();
+        let receiver = aborts.borrow_mut().receivers.remove(&id);
+        receiver
+    };
+    return match receiver {
+        Some(receiver) => match receiver.await {
+            Ok(true) => String::from("timeout"),
+            Ok(false) => String::new(),
+            Err(_) => String::from("disconnect"),
+        },
+        None => String::new(),
+    };
+}
+
+#[op2(async)]
+async fn op_sleep(ms: u32) {
+    sleep(Duration::from_millis(ms.into())).await;
+}
+
+deno_core::extension!(
+    my_extension,
+    ops = [
+        op_route,
+        op_configure,
+        op_read_upload,
+        op_static_dir,
+        op_sleep,
+        op_wait_abort,
+        op_stream_create,
+        op_stream_write,
+        op_stream_close,
+        op_log,
+    ],
+    js = ["src/runtime.js"]
+);
+
+// runtime.js and the JS of the extensions below, evaluated by build.rs, so a
+// runtime only has to run the app's own modules. The app is not snapshotted:
+// its top-level code connects databases and registers routes, static dirs and
+// config in OpState, none of which a V8 snapshot carries, so each worker still
+// evaluates the entry. An opt-in app snapshot would have to replay those
+// registrations from JS and is left for a follow-up.
+static RUNTIME_SNAPSHOT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/RUNTIME_SNAPSHOT.bin"));
+
+// the extension names build.rs snapshotted, one per line and in order
+static SNAPSHOT_EXTENSIONS: &str =
+    include_str!(concat!(env!("OUT_DIR"), "/SNAPSHOT_EXTENSIONS.txt"));
+
+// A runtime whose extensions don't line up with the snapshot would pair ops
+// with the wrong JS, so a list edited in only one place fails right away.
+fn check_snapshot_extensions(extensions: &[Extension]) -> Result<(), AnyError> {
+    let names = extensions.iter().map(|ext| ext.name).collect::<Vec<_>>();
+    let snapshotted = SNAPSHOT_EXTENSIONS.lines().collect::<Vec<_>>();
+    if names != snapshotted {
+        bail!(
+            "the runtime extensions [{}] don't match the ones in the snapshot [{}], update build.rs",
+            names.join(", "),
+            snapshotted.join(", ")
+        );
+    }
+    return Ok(());
+}
+
+pub struct JsRunnerInner {
+    pub routes: HashMap<String, RouteEntry>,
+    pub static_dirs: Vec<StaticDir>,
+    pub config: AppConfig,
+    // compiled only when `validateResponses` is configured
+    response_schemas: HashMap<String, JSONSchema>,
+    runtime: Rc<RefCell<JsRuntime>>,
+    cache: Arc<DataCache>,
+    event_loop_wake: Notify,
+    // db_pool: Pool<Sqlite>,
+}
+
+#[derive(Clone)]
+pub struct JsRunner {
+    inner: Rc<JsRunnerInner>,
+}
+
+impl std::ops::Deref for JsRunner {
+    type Target = JsRunnerInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl JsRunner {
+    pub async fn new(
+        entry: &Path,
+        tx_req: Option<mpsc::WeakSender<RouteRequest>>,
+        plugins: &[Arc<dyn Plugin>],
+        transpile_cache: Option<&Path>,
+        cache: Arc<DataCache>,
+    ) -> Result<JsRunner, AnyError> {
+        let init_module = deno_core::resolve_path(entry, env::current_dir()?.as_path())?;
+        let entry_file = init_module.to_file_path().unwrap();
+        let config_file = read_config_file(&entry_file)?;
+        let module_loader = ScriptModuleLoader::new(&entry_file, &config_file, transpile_cache)?;
+        let plugin_extensions = plugins
+            .iter()
+            .map(|plugin| plugin.extension())
+            .collect::<Vec<_>>();
+        // the snapshot only has the built-in JS, so plugin scripts are run below
+        let mut plugin_scripts = vec![];
+        for extension in &plugin_extensions {
+            if !extension.esm_files.is_empty() {
+                bail!(
+                    "plugin extension {} has ES modules, only `js` scripts are supported",
+                    extension.name
+                );
+            }
+            for file in extension.js_files.iter() {
+                plugin_scripts.push((file.specifier, file.load()?));
+            }
+        }
+        // same order as in build.rs; the JS is already in the snapshot
+        let builtin_extensions = vec![
+            my_extension::init_ops(),
+            datacache_extension::init_ops(),
+            database_extension::init_ops(),
+            websocket_extension::init_ops(),
+            timers_extension::init_ops(),
+            fetch_extension::init_ops(),
+            metrics_extension::init_ops(),
+        ];
+        check_snapshot_extensions(&builtin_extensions)?;
+        let mut js_runtime = deno_core::JsRuntime::new(deno_core::RuntimeOptions {
+            module_loader: Some(Rc::new(module_loader)),
+            startup_snapshot: Some(RUNTIME_SNAPSHOT),
+            extensions: builtin_extensions
+                .into_iter()
+                .chain(plugin_extensions)
+                .collect(),
+            ..Default::default()
+        });
+        // following https://github.com/DataDog/datadog-static-analyzer/blob/cde26f42f1cdbbeb09650403318234f277138bbd/crates/static-analysis-kernel/src/analysis/ddsa_lib/runtime.rs#L54
+
+        let route_map: HashMap<String, RouteEntry> = HashMap::new();
+
+        let hmref = Rc::new(RefCell::new(route_map));
+        let txref = Rc::new(RefCell::new(tx_req));
+        let dirsref: Rc<RefCell<Vec<StaticDir>>> = Rc::new(RefCell::new(vec![]));
+        let streamsref = Rc::new(RefCell::new(ResponseStreams::default()));
+        let configref = Rc::new(RefCell::new(AppConfig::default().merge(config_file)?));
+        let abortsref = Rc::new(RefCell::new(RequestAborts::default()));
+
+        js_runtime.op_state().borrow_mut().put(Rc::clone(&hmref));
+        js_runtime.op_state().borrow_mut().put(Rc::clone(&txref));
+        js_runtime.op_state().borrow_mut().put(Arc::clone(&cache));
+        js_runtime.op_state().borrow_mut().put(Rc::clone(&dirsref));
+        js_runtime.op_state().borrow_mut().put(streamsref);
+        js_runtime
+            .op_state()
+            .borrow_mut()
+            .put(Rc::clone(&configref));
+        js_runtime.op_state().borrow_mut().put(abortsref);
+        js_runtime
+            .op_state()
+            .borrow_mut()
+            .put(Rc::new(RefCell::new(RequestSpans::new())));
+        for plugin in plugins {
+            plugin.init_state(&mut js_runtime.op_state().borrow_mut());
+        }
+        for (specifier, code) in plugin_scripts {
+            js_runtime.execute_script(specifier, code)?;
+        }
+
+        let mod_id = js_runtime.load_main_es_module(&init_module).await?;
+        let result = js_runtime.mod_evaluate(mod_id);
+        js_runtime.run_event_loop(Default::default()).await?;
+        result.await?;
+
+        let routes = (*hmref.borrow()).clone();
+        let config = (*configref.borrow()).clone();
+        let mut response_schemas = HashMap::new();
+        if config.validate_responses {
+            for (name, entry) in &routes {
+                let schema = entry.options.schema.as_ref();
+                if let Some(response) = schema.and_then(|schema| schema.response.as_ref()) {
+                    response_schemas.insert(name.clone(), compile_schema(name, response)?);
+                }
+            }
+        }
+
+        return Ok(JsRunner {
+            inner: Rc::new(JsRunnerInner {
+                routes,
+                static_dirs: (*dirsref.borrow()).clone(),
+                config,
+                response_schemas,
+                runtime: Rc::new(RefCell::new(js_runtime)),
+                cache,
+                event_loop_wake: Notify::new(),
+            }),
+        });
+    }
+
+    async fn run_loop(&self, mut rx_req: mpsc::Receiver<RouteRequest>) {
+        let local = task::LocalSet::new();
+        local
+            .run_until(async move {
+                let driver = self.clone();
+                task::spawn_local(async move { driver.drive_event_loop().await });
+                let worker = worker_label();
+                let in_flight = metrics().in_flight.with_label_values(&[&worker]);
+                while let Some(mut req) = rx_req.recv().await {
+                    let this = self.clone();
+                    let span = handler_span(req.span.as_ref(), &req.request_id, &req.route_name);
+                    req.context = this.register_request_span(span.clone());
+                    let in_flight = in_flight.clone();
+                    in_flight.inc();
+                    let handle = async move {
+                        let response = this.run_route(&mut req).await;
+                        this.unregister_request_span(req.context);
+                        in_flight.dec();
+                        this.record_heap_usage();
+                        if let Some(resp_chan) = req.response_channel {
+                            // the client may have gone or timed out meanwhile
+                            let _ = resp_chan.send(response);
+                        }
+                        this.event_loop_wake.notify_one();
+
+                        // ...
+                    };
+                    task::spawn_local(handle.instrument(span));
+                }
+            })
+            .await;
+    }
+
+    // Keeps polling the event loop between requests so that work which outlives
+    // a handler (e.g. onCacheChange callbacks) makes progress. Handlers polling
+    // the loop themselves steal its waker, so they wake us when they finish.
+    async fn drive_event_loop(&self) {
+        loop {
+            let runtime = unsafe { &mut *self.runtime.as_ptr() };
+            let poll_loop = poll_fn(|cx| {
+                if let Poll::Ready(Err(e)) = runtime.poll_event_loop(cx, Default::default()) {
+                    tracing::error!("{:#}", e);
+                }
+                Poll::<()>::Pending
+            });
+            tokio::select! {
+                _ = poll_loop => {}
+                _ = self.event_loop_wake.notified() => {}
+            }
+        }
+    }
+
+    #[tokio::main(flavor = "current_thread")]
+    async fn run_thread(
+        entry: PathBuf,
+        plugins: Vec<Arc<dyn Plugin>>,
+        transpile_cache: Option<PathBuf>,
+        cache: Arc<DataCache>,
+        tx_req: mpsc::WeakSender<RouteRequest>,
+        rx_req: mpsc::Receiver<RouteRequest>,
+        ready: oneshot::Sender<Result<(), AnyError>>,
+    ) {
+        let transpile_cache = transpile_cache.as_deref();
+        let runner = JsRunner::new(&entry, Some(tx_req), &plugins, transpile_cache, cache);
+        let runner = match runner.await {
+            Ok(runner) => runner,
+            Err(e) => {
+                let _ = ready.send(Err(e));
+                return;
+            }
+        };
+        let _ = ready.send(Ok(()));
+        runner.run_loop(rx_req).await;
+    }
+
+    // The thread name labels the worker's metrics. The receiver resolves once
+    // the worker has loaded the app, or with the error that stopped it. The
+    // worker only keeps a weak handle on its own queue, so it stops once the
+    // returned sender is dropped.
+    pub fn spawn_thread(
+        index: usize,
+        entry: PathBuf,
+        plugins: Vec<Arc<dyn Plugin>>,
+        transpile_cache: Option<PathBuf>,
+        cache: Arc<DataCache>,
+    ) -> Result<
+        (
+            mpsc::Sender<RouteRequest>,
+            oneshot::Receiver<Result<(), AnyError>>,
+        ),
+        AnyError,
+    > {
+        let (tx_req, rx_req) = mpsc::channel(128);
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let tx_req1 = tx_req.downgrade();
+        thread::Builder::new()
+            .name(format!("js-worker-{}", index))
+            .spawn(move || {
+                JsRunner::run_thread(
+                    entry,
+                    plugins,
+                    transpile_cache,
+                    cache,
+                    tx_req1,
+                    rx_req,
+                    ready_tx,
+                );
+            })?;
+        return Ok((tx_req, ready_rx));
+    }
+
+    async fn call_handler(
+        &self,
+        handler: &v8::Global<v8::Function>,
+        args: Vec<Value>,
+    ) -> Result<v8::Global<v8::Value>, RouteError> {
+        let func_res_promise = {
+            let runtime = unsafe { &mut *self.runtime.as_ptr() };
+            let args = {
+                let scope = &mut runtime.handle_scope();
+                args.into_iter()
+                    .map(|arg| {
+                        let v8_arg: v8::Local<v8::Value> = to_v8(scope, arg).unwrap();
+                        v8::Global::new(scope, v8_arg)
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            runtime.call_with_args(handler, &args)
+        };
+
+        let func_res0 = unsafe { &mut *self.runtime.as_ptr() }
+            .with_event_loop_promise(func_res_promise, Default::default())
+            .await;
+
+        return func_res0.map_err(RouteError::Failed);
+    }
+
+    async fn run_route_value(
+        &self,
+        req: &mut RouteRequest,
+    ) -> Result<v8::Global<v8::Value>, RouteError> {
+        let hm = &self.routes;
+
+        if let Some(entry) = hm.get(&*(req.route_name)) {
+            let socket = req.websocket.take().map(|channels| {
+                let runtime = unsafe { &mut *self.runtime.as_ptr() };
+                register_websocket(&runtime.op_state().borrow(), channels)
+            });
+            let abort = req
+                .abort
+                .take()
+                .map(|receiver| self.register_abort(receiver));
+            let mut jsreq = request_json(req, socket);
+            jsreq["abortId"] = json!(abort);
+            let res = self.call_handler(&entry.handler, vec![jsreq]).await;
+            if let Some(id) = abort {
+                self.unregister_abort(id);
+            }
+            return res;
+        } else {
+            return Err(RouteError::NotFound);
+        }
+    }
+
+    fn record_heap_usage(&self) {
+        let runtime = unsafe { &mut *self.runtime.as_ptr() };
+        let mut stats = v8::HeapStatistics::default();
+        runtime.v8_isolate().get_heap_statistics(&mut stats);
+        let worker = worker_label();
+        let metrics = metrics();
+        metrics
+            .heap_used
+            .with_label_values(&[&worker])
+            .set(stats.used_heap_size() as i64);
+        metrics
+            .heap_total
+            .with_label_values(&[&worker])
+            .set(stats.total_heap_size() as i64);
+    }
+
+    fn register_abort(&self, receiver: oneshot::Receiver<bool>) -> u32 {
+        let runtime = unsafe { &mut *self.runtime.as_ptr() };
+        let op_state = runtime.op_state();
+        let op_state = op_state.borrow();
+        let mut aborts = op_state.borrow::<Rc<RefCell<RequestAborts>>>().borrow_mut();
+        aborts.next_id += 1;
+        let id = aborts.next_id;
+        aborts.receivers.insert(id, receiver);
+        return id;
+    }
+
+    fn register_request_span(&self, span: tracing::Span) -> u32 {
+        let runtime = unsafe { &mut *self.runtime.as_ptr() };
+        let op_state = runtime.op_state();
+        let op_state = op_state.borrow();
+        let spans = op_state.borrow::<Rc<RefCell<RequestSpans>>>();
+        return spans.borrow_mut().insert(span);
+    }
+
+    fn unregister_request_span(&self, id: u32) {
+        let runtime = unsafe { &mut *self.runtime.as_ptr() };
+        let op_state = runtime.op_state();
+        let op_state = op_state.borrow();
+        let spans = op_state.borrow::<Rc<RefCell<RequestSpans>>>();
+        spans.borrow_mut().remove(id);
+    }
+
+    // for handlers that never waited on their signal
+    fn unregister_abort(&self, id: u32) {
+        let runtime = unsafe { &mut *self.runtime.as_ptr() };
+        let op_state = runtime.op_state();
+        let op_state = op_state.borrow();
+        let aborts = op_state.borrow::<Rc<RefCell<RequestAborts>>>();
+        aborts.borrow_mut().receivers.remove(&id);
+    }
+
+    async fn rebuild_cache(&self, req: &mut RouteRequest) {
+        let started = Instant::now();
+        let res = self.run_route_value(req).await;
+        let built = res.and_then(|func_res| {
+            let runtime = unsafe { &mut *self.runtime.as_ptr() };
+            let scope = &mut runtime.handle_scope();
+            let v8_val = v8::Local::new(scope, func_res);
+            let serde_val: Value =
+                from_v8(scope, v8_val).map_err(|e| RouteError::Failed(e.into()))?;
+            Ok(serde_val)
+        });
+        match built {
+            Ok(serde_val) => {
+                //save to global
+                self.cache.set(serde_val);
+                self.cache.record_build(started.elapsed(), None);
+            }
+            Err(RouteError::Failed(e)) => {
+                self.cache
+                    .record_build(started.elapsed(), Some(e.to_string()));
+                tracing::error!("building the data cache failed: {:#}", e);
+            }
+            Err(RouteError::NotFound) => {}
+        }
+    }
+
+    fn render_response(
+        &self,
+        func_res1: v8::Global<v8::Value>,
+        response_schema: Option<&JSONSchema>,
+    ) -> Result<Response<Body>, RouteError> {
+        let runtime = unsafe { &mut *self.runtime.as_ptr() };
+        let scope = &mut runtime.handle_scope();
+        let func_res = func_res1.open(scope);
+
+        if func_res.is_string() {
+            let s = func_res
+                .to_string(scope)
+                .unwrap()
+                .to_rust_string_lossy(scope);
+            return Ok(Html(s).into_response());
+        }
+        let lres = v8::Local::new(scope, func_res1);
+        let res: serde_json::Map<String, Value> =
+            from_v8(scope, lres).map_err(|e| RouteError::Failed(e.into()))?;
+        if let Some(id) = res.get("stream").and_then(|id| id.as_u64()) {
+            return Ok(annotate_response(&res, self.stream_response(id as u32)));
+        }
+        if let Some(events) = res.get("cacheEvents") {
+            let with_diff = events.get("diff") == Some(&Value::Bool(true));
+            return Ok(annotate_response(
+                &res,
+                self.cache.events_response(with_diff),
+            ));
+        }
+        if let (Some(schema), Some(body)) = (response_schema, res.get("json")) {
+            let violations = validate(schema, "response", body);
+            if !violations.is_empty() {
+                return Ok(problem_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "response validation failed",
+                    violations,
+                ));
+            }
+        }
+        if res.contains_key("json") {
+            return Ok(annotate_response(
+                &res,
+                Json(res.get("json")).into_response(),
+            ));
+        }
+        if let Some(html) = res.get("html") {
+            let body: String =
+                serde_json::from_value(html.clone()).map_err(|e| RouteError::Failed(e.into()))?;
+            return Ok(annotate_response(&res, Html(body).into_response()));
+        }
+
+        return Ok(annotate_response(&res, Html("").into_response()));
+    }
+
+    async fn run_route(&self, req: &mut RouteRequest) -> Response<Body> {
+        if req.route_name == "__create_cache" {
+            self.rebuild_cache(req).await;
+            return Html("").into_response();
+        }
+        if req.websocket.is_some() {
+            // axum has answered the upgrade already, there is nothing to render
+            if let Err(RouteError::Failed(e)) = self.run_route_value(req).await {
+                tracing::error!("the websocket handler failed: {:#}", e);
+            }
+            return Html("").into_response();
+        }
+        let response_schema = self.response_schemas.get(&req.route_name);
+        let res = self
+            .run_route_value(req)
+            .await
+            .and_then(|func_res| self.render_response(func_res, response_schema));
+        let resp = match res {
+            Ok(resp) => resp,
+            Err(RouteError::Failed(e)) if self.routes.contains_key("__on_error") => {
+                self.run_error_handler(req, e).await
+            }
+            Err(e) => e.into_response(),
+        };
+        self.discard_streams(req.context);
+        return resp;
+    }
+
+    // Drops the streams a request created but that never became its response
+    // body, so their producers stop instead of waiting for a reader.
+    fn discard_streams(&self, context: u32) {
+        let runtime = unsafe { &mut *self.runtime.as_ptr() };
+        let op_state = runtime.op_state();
+        let op_state = op_state.borrow();
+        let streams = op_state.borrow::<Rc<RefCell<ResponseStreams>>>();
+        streams
+            .borrow_mut()
+            .receivers
+            .retain(|_, (owner, _)| *owner != context);
+    }
+
+    // Renders a failed request through the `onError` handler, falling back to
+    // the built-in error page when the handler itself fails.
+    async fn run_error_handler(&self, req: &RouteRequest, e: AnyError) -> Response<Body> {
+        let entry = &self.routes["__on_error"];
+        let args = vec![error_json(&e), request_json(req, None)];
+        let res = self
+            .call_handler(&entry.handler, args)
+            .await
+            .and_then(|func_res| self.render_response(func_res, None));
+        match res {
+            Ok(resp) => resp,
+            Err(RouteError::Failed(handler_err)) => {
+                tracing::error!("the onError handler failed: {:#}", handler_err);
+                RouteError::Failed(e).into_response()
+            }
+            Err(RouteError::NotFound) => RouteError::Failed(e).into_response(),
+        }
+    }
+
+    fn stream_response(&self, id: u32) -> Response<Body> {
+        let runtime = unsafe { &mut *self.runtime.as_ptr() };
+        let op_state = runtime.op_state();
+        let op_state = op_state.borrow();
+        let streams = op_state.borrow::<Rc<RefCell<ResponseStreams>>>();
+        let rx = streams.borrow_mut().receivers.remove(&id);
+        match rx {
+            Some((_, rx)) => {
+                let body = futures::stream::unfold(rx, |mut rx| async move {
+                    let chunk = rx.recv().await?;
+                    Some((Ok::<_, Infallible>(chunk), rx))
+                });
+                (
+                    [(CONTENT_TYPE, "text/plain; charset=utf-8")],
+                    Body::from_stream(body),
+                )
+                    .into_response()
+            }
+            None => RouteError::Failed(deno_core::anyhow::anyhow!("unknown stream {}", id))
+                .into_response(),
+        }
+    }
+
+    // Returns true when the cache was restored from a persisted snapshot and
+    // still has to be rebuilt in the background.
+    pub async fn populate_initial_cache(&self) -> bool {
+        if self.inner.routes.contains_key("__create_cache") {
+            if self.cache.load_persisted() {
+                return true;
+            }
+            //let (tx, _) = oneshot::channel();
+            let mut req = RouteRequest {
+                route_name: String::from("__create_cache"),
+                ..Default::default()
+            };
+            self.run_route(&mut req).await;
+        }
+        return false;
+    }
+}
+
+fn request_json(req: &RouteRequest, socket: Option<u32>) -> Value {
+    let route = split_route_key(&req.route_name).map_or(req.route_name.as_str(), |(_, path)| path);
+    return json!({
+        "params": req.route_args,
+        "route": route,
+        "method": req.method,
+        "path": req.path,
+        "query": req.query,
+        "headers": req.headers,
+        "socket": socket,
+        "body": req.body,
+        "context": req.context,
+    });
+}
+
+fn error_json(e: &AnyError) -> Value {
+    match e.downcast_ref::<JsError>() {
+        Some(js_error) => json!({
+            "name": js_error.name.clone().unwrap_or(String::from("Error")),
+            "message": js_error
+                .message
+                .clone()
+                .unwrap_or(js_error.exception_message.clone()),
+            "stack": js_error.stack,
+        }),
+        None => json!({ "name": "Error", "message": e.to_string() }),
+    }
+}
+
+fn annotate_response(
+    resp_obj: &serde_json::Map<String, Value>,
+    resp: Response<Body>,
+) -> Response<Body> {
+    let mut resp1 = if resp_obj.contains_key("status") {
+        let code: u16 = serde_json::from_value(resp_obj.get("status").unwrap().clone()).unwrap();
+        let scode = StatusCode::from_u16(code).unwrap();
+        (scode, resp).into_response()
+    } else {
+        resp
+    };
+    if let Some(Value::Object(headers)) = resp_obj.get("headers") {
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes());
+            let value = value.as_str().map(HeaderValue::from_str);
+            if let (Ok(name), Some(Ok(value))) = (name, value) {
+                resp1.headers_mut().insert(name, value);
+            }
+        }
+    }
+    return resp1;
+}
@@ -7,15 +7,424 @@
       .join(" ");
   }
 
-  globalThis.console = {
-    log: (...args) => {
-      core.print(`${argsToMessage(...args)}\n`, false);
-    },
-    error: (...args) => {
-      core.print(`[err]: ${argsToMessage(...args)}\n`, true);
+  // The request whose code is running, 0 outside of requests. Handlers
+  // share one event loop, so every promise remembers the request it was
+  // created for and the hooks switch back to it when its reactions run.
+  let currentContext = 0;
+  const promiseContexts = new WeakMap();
+  let promiseHooksSet = false;
+
+  // set on the first request, hooks set while the snapshot is taken are lost
+  function trackContexts() {
+    if (promiseHooksSet) return;
+    promiseHooksSet = true;
+    core.setPromiseHooks(
+      (promise, parent) => {
+        const context = currentContext || promiseContexts.get(parent);
+        if (context) promiseContexts.set(promise, context);
+      },
+      (promise) => {
+        currentContext = promiseContexts.get(promise) ?? 0;
+      },
+      () => {
+        currentContext = 0;
+      },
+      null
+    );
+  }
+
+  function inContext(context, fn) {
+    trackContexts();
+    const outer = currentContext;
+    currentContext = context;
+    try {
+      return fn();
+    } finally {
+      currentContext = outer;
+    }
+  }
+
+  // written through the server's log with the level as given; `context`
+  // names the request the lines belong to, by default the current one
+  const log =
+    (level, context) =>
+    (...args) =>
+      core.ops.op_log(level, argsToMessage(...args), context ?? currentContext);
+  const logger = (context) => ({
+    debug: log("debug", context),
+    info: log("info", context),
+    warn: log("warn", context),
+    error: log("error", context),
+  });
+
+  // lines logged while a handler runs carry its request id, method and route
+  globalThis.console = { ...logger(), log: log("info") };
+
+  const abortSignal = Symbol("abortSignal");
+
+  class AbortSignal {
+    aborted = false;
+    reason = undefined;
+    onabort = null;
+    #listeners = [];
+
+    static abort(reason) {
+      const controller = new AbortController();
+      controller.abort(reason);
+      return controller.signal;
+    }
+
+    static timeout(ms) {
+      const controller = new AbortController();
+      const timer = setTimeout(() => {
+        const reason = new Error("The operation timed out");
+        reason.name = "TimeoutError";
+        controller.abort(reason);
+      }, ms);
+      Deno.unrefTimer(timer);
+      return controller.signal;
+    }
+
+    addEventListener(type, listener) {
+      if (type === "abort") this.#listeners.push(listener);
+    }
+
+    removeEventListener(type, listener) {
+      this.#listeners = this.#listeners.filter((l) => l !== listener);
+    }
+
+    throwIfAborted() {
+      if (this.aborted) throw this.reason;
+    }
+
+    [abortSignal](reason) {
+      if (this.aborted) return;
+      this.aborted = true;
+      if (reason === undefined) {
+        reason = new Error("This operation was aborted");
+        reason.name = "AbortError";
+      }
+      this.reason = reason;
+      const event = { type: "abort", target: this };
+      for (const listener of [this.onabort, ...this.#listeners]) {
+        try {
+          listener?.call(this, event);
+        } catch (e) {
+          console.error("abort listener failed", String(e));
+        }
+      }
+    }
+  }
+
+  class AbortController {
+    signal = new AbortSignal();
+
+    abort(reason) {
+      this.signal[abortSignal](reason);
+    }
+  }
+
+  globalThis.AbortSignal = AbortSignal;
+  globalThis.AbortController = AbortController;
+
+  function isStreamable(value) {
+    return (
+      value != null &&
+      typeof value !== "string" &&
+      (typeof value[Symbol.asyncIterator] === "function" ||
+        typeof value.getReader === "function")
+    );
+  }
+
+  async function* readStream(stream) {
+    const reader = stream.getReader();
+    try {
+      while (true) {
+        const { done, value } = await reader.read();
+        if (done) return;
+        yield value;
+      }
+    } finally {
+      reader.releaseLock();
+    }
+  }
+
+  // string and binary chunks as a Uint8Array, other values through `other`
+  function toBytes(chunk, other) {
+    if (typeof chunk === "string") return core.encode(chunk);
+    if (chunk instanceof Uint8Array) return chunk;
+    if (chunk instanceof ArrayBuffer) return new Uint8Array(chunk);
+    if (ArrayBuffer.isView(chunk)) {
+      return new Uint8Array(chunk.buffer, chunk.byteOffset, chunk.byteLength);
+    }
+    return other(chunk);
+  }
+
+  // fetch.js is evaluated after this script and reads request bodies the same way
+  Object.defineProperty(globalThis, "__bodyChunks", {
+    value: { readStream, toBytes },
+  });
+
+  const jsonBytes = (value) => core.encode(JSON.stringify(value));
+
+  // Writes the chunks into the response body; every write waits until the
+  // client has room for it, and the source is closed once the client leaves.
+  async function pumpStream(id, source) {
+    const iterable =
+      typeof source.getReader === "function" ? readStream(source) : source;
+    try {
+      for await (const chunk of iterable) {
+        const bytes = toBytes(chunk, jsonBytes);
+        if (!(await Deno.core.ops.op_stream_write(id, bytes))) break;
+      }
+    } catch (e) {
+      console.error("response stream failed", String(e));
+    } finally {
+      Deno.core.ops.op_stream_close(id);
+    }
+  }
+
+  function toStreamResponse(res, context) {
+    const source = isStreamable(res) ? res : res.stream;
+    const id = Deno.core.ops.op_stream_create(context);
+    pumpStream(id, source);
+    return isStreamable(res) ? { stream: id } : { ...res, stream: id };
+  }
+
+  const middlewares = [];
+  const groupPrefixes = [];
+
+  function withGroupPrefix(path) {
+    const prefix = groupPrefixes.join("");
+    if (!prefix || !path.startsWith("/")) return path;
+    return path === "/" ? prefix : `${prefix}${path}`;
+  }
+
+  function matchesPrefix(prefix, path) {
+    if (prefix === "/" || path === prefix) return true;
+    return path.startsWith(prefix.endsWith("/") ? prefix : `${prefix}/`);
+  }
+
+  // Runs the middlewares registered for this path in registration order. Each
+  // one gets `next()` to continue down the chain, and may return its own
+  // response instead or change the one `next()` resolves to.
+  function runMiddlewares(handler, req) {
+    const chain = middlewares.filter(({ prefix }) =>
+      matchesPrefix(prefix, req.path)
+    );
+    const dispatch = async (i) => {
+      if (i === chain.length) {
+        const res = await handler(req);
+        return typeof res === "string" ? { html: res } : res;
+      }
+      let called = false;
+      return await chain[i].middleware(req, () => {
+        if (called) throw new Error("next() called multiple times");
+        called = true;
+        return dispatch(i + 1);
+      });
+    };
+    return dispatch(0);
+  }
+
+  globalThis.use = (prefix, middleware) => {
+    if (typeof prefix === "function") {
+      middleware = prefix;
+      prefix = "/";
+    }
+    middlewares.push({ prefix: withGroupPrefix(prefix), middleware });
+  };
+
+  // `context` lets Rust drop a stream whose response is never sent
+  function finishResponse(res, context) {
+    if (isStreamable(res) || isStreamable(res?.stream)) {
+      return toStreamResponse(res, context);
+    }
+    return res;
+  }
+
+  async function respond(handler, rawReq) {
+    const req = toRequest(rawReq);
+    return finishResponse(
+      await inContext(rawReq.context, () =>
+        middlewares.length > 0 ? runMiddlewares(handler, req) : handler(req)
+      ),
+      rawReq.context
+    );
+  }
+
+  function withDefaultStatus(res, status) {
+    if (typeof res === "string") return { html: res, status };
+    return { status, ...res };
+  }
+
+  function sseFrame(event) {
+    if (typeof event !== "object" || event === null) {
+      event = { data: event };
+    }
+    let frame = "";
+    if (event.event) frame += `event: ${event.event}\n`;
+    if (event.id !== undefined) frame += `id: ${event.id}\n`;
+    if (event.retry !== undefined) frame += `retry: ${event.retry}\n`;
+    const data =
+      typeof event.data === "string" ? event.data : JSON.stringify(event.data);
+    for (const line of data.split("\n")) frame += `data: ${line}\n`;
+    return `${frame}\n`;
+  }
+
+  async function* sseFrames(source) {
+    const iterable =
+      typeof source.getReader === "function" ? readStream(source) : source;
+    for await (const event of iterable) {
+      yield sseFrame(event);
+    }
+  }
+
+  globalThis.sse = (source, options = {}) => ({
+    ...options,
+    stream: sseFrames(source),
+    headers: {
+      "content-type": "text/event-stream",
+      "cache-control": "no-cache",
+      ...options.headers,
     },
+  });
+
+  function uploadedPart(part) {
+    if (part.path === undefined) return part;
+    const bytes = () => core.ops.op_read_upload(part.path);
+    return { ...part, bytes, text: async () => core.decode(await bytes()) };
+  }
+
+  function abortError(reason) {
+    const error = new Error(
+      reason === "timeout"
+        ? "The request timed out"
+        : "The client closed the connection"
+    );
+    error.name = reason === "timeout" ? "TimeoutError" : "AbortError";
+    return error;
+  }
+
+  // aborted when the request times out or the client disconnects
+  function requestSignal(abortId) {
+    const controller = new AbortController();
+    if (abortId != null) {
+      const wait = core.ops.op_wait_abort(abortId);
+      // a pending request must not keep the event loop alive
+      core.unrefOpPromise(wait);
+      wait.then((reason) => {
+        if (reason) controller.abort(abortError(reason));
+      });
+    }
+    return controller.signal;
+  }
+
+  // Rust reads the body before calling into JS; `req.body` is replaced by
+  // the `text()`, `json()`, `form()` and `multipart()` helpers.
+  function toRequest(req) {
+    const { body, abortId, context, ...rest } = req;
+    const text = body?.text ?? "";
+    // the request's spans are the parents of those of its queries and fetches
+    return Object.assign(rest, {
+      log: logger(context),
+      db: {
+        query: (sql, pars = []) => core.ops.op_query(sql, pars, context),
+        execute: (sql, pars = []) => core.ops.op_execute(sql, pars, context),
+      },
+      fetch: (input, init = {}) => fetch(input, { ...init, context }),
+      signal: requestSignal(abortId),
+      text: async () => text,
+      json: async () => JSON.parse(text),
+      form: async () => body?.form ?? {},
+      multipart: async () => (body?.parts ?? []).map(uploadedPart),
+    });
+  }
+
+  // `method` is a method name or a list of them, GET by default
+  function normalizeMethods({ method, ...options }) {
+    if (method === undefined) return options;
+    return { ...options, methods: Array.isArray(method) ? method : [method] };
+  }
+
+  globalThis.route = (path, options, handler) => {
+    if (typeof options === "function") {
+      handler = options;
+      options = {};
+    }
+    options = normalizeMethods(options ?? {});
+    Deno.core.ops.op_route(
+      withGroupPrefix(path),
+      options,
+      options.websocket
+        ? handler
+        : (req) => respond(handler, req)
+    );
+  };
+
+  globalThis.configure = (options) => Deno.core.ops.op_configure(options);
+
+  const applyRouter = Symbol("applyRouter");
+
+  // A sub-application: records registrations so that another module can
+  // mount them with `group(prefix, app)`.
+  globalThis.router = () => {
+    const registrations = [];
+    const record =
+      (register) =>
+      (...args) => {
+        registrations.push(() => register(...args));
+        return app;
+      };
+    const app = {
+      route: record((...args) => globalThis.route(...args)),
+      websocket: record((...args) => globalThis.websocket(...args)),
+      use: record((...args) => globalThis.use(...args)),
+      group: record((...args) => globalThis.group(...args)),
+      [applyRouter]: () => registrations.forEach((register) => register()),
+    };
+    return app;
+  };
+
+  globalThis.group = (prefix, routes) => {
+    groupPrefixes.push(prefix.endsWith("/") ? prefix.slice(0, -1) : prefix);
+    try {
+      if (typeof routes === "function") {
+        routes();
+      } else {
+        routes[applyRouter]();
+      }
+    } finally {
+      groupPrefixes.pop();
+    }
   };
+  globalThis.notFound = (handler) => {
+    Deno.core.ops.op_route("__not_found", {}, async (req) =>
+      withDefaultStatus(await respond(handler, req), 404)
+    );
+  };
+
+  // the handler gets `{ name, message, stack }` and the request that failed
+  globalThis.onError = (handler) => {
+    Deno.core.ops.op_route("__on_error", {}, async (err, req) =>
+      withDefaultStatus(
+        finishResponse(
+          await inContext(req.context, () => handler(err, toRequest(req))),
+          req.context
+        ),
+        500
+      )
+    );
+  };
+
+  // RFC 7807 problem details response
+  globalThis.problem = ({ status = 500, title, detail, type, instance, ...rest }) => ({
+    status,
+    json: { type: type ?? "about:blank", title, status, detail, instance, ...rest },
+    headers: { "content-type": "application/problem+json" },
+  });
 
-  globalThis.route = Deno.core.ops.op_route;
-  globalThis.sleep = Deno.core.ops.op_sleep;
+  globalThis.staticDir = (prefix, dir, options) =>
+    Deno.core.ops.op_static_dir(prefix, dir, options ?? {});
+  globalThis.sleep = (ms) => Deno.core.ops.op_sleep(ms);
 })(globalThis);
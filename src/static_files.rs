@@ -0,0 +1,80 @@
+use axum::extract::Request;
+use axum::http::header::CACHE_CONTROL;
+use axum::http::HeaderValue;
+use axum::response::IntoResponse;
+use axum::Router;
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::path::Path;
+use tower::Service;
+use tower_http::services::{ServeDir, ServeFile};
+use tower_http::set_header::SetResponseHeader;
+
+// options passed as the optional third argument of `staticDir(prefix, dir, options)`
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StaticDirOptions {
+    // Cache-Control max-age in seconds
+    pub max_age: Option<u64>,
+    // serve index.html for paths that do not match a file
+    pub spa: bool,
+}
+
+#[derive(Clone)]
+pub struct StaticDir {
+    pub prefix: String,
+    pub dir: String,
+    pub options: StaticDirOptions,
+}
+
+// the paths axum registers for a directory, checked for conflicts with the
+// JS routes before mounting
+pub fn static_dir_paths(static_dir: &StaticDir) -> Vec<String> {
+    let prefix = static_dir.prefix.trim_end_matches('/');
+    if prefix.is_empty() {
+        // mounted as the fallback, so it cannot conflict with a route
+        return vec![];
+    }
+    return vec![
+        String::from(prefix),
+        format!("{}/", prefix),
+        format!("{}/*rest", prefix),
+    ];
+}
+
+// "/" becomes the router's fallback, any other prefix a nested service
+fn mount_service<S>(router: Router, prefix: &str, service: S) -> Router
+where
+    S: Service<Request, Error = Infallible> + Clone + Send + 'static,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+{
+    if prefix == "/" {
+        return router.fallback_service(service);
+    }
+    return router.nest_service(prefix, service);
+}
+
+// JS routes always win: they are registered as exact paths, which the router
+// prefers over the wildcard a nested service is mounted on.
+pub fn mount_static_dir(router: Router, static_dir: &StaticDir) -> Router {
+    let serve_dir = ServeDir::new(&static_dir.dir)
+        .precompressed_gzip()
+        .precompressed_br();
+    let cache_control = match static_dir.options.max_age {
+        Some(max_age) => HeaderValue::from_str(&format!("public, max-age={}", max_age)).unwrap(),
+        None => HeaderValue::from_static("no-cache"),
+    };
+    if static_dir.options.spa {
+        let index = Path::new(&static_dir.dir).join("index.html");
+        let service = SetResponseHeader::if_not_present(
+            serve_dir.fallback(ServeFile::new(index)),
+            CACHE_CONTROL,
+            cache_control,
+        );
+        mount_service(router, &static_dir.prefix, service)
+    } else {
+        let service = SetResponseHeader::if_not_present(serve_dir, CACHE_CONTROL, cache_control);
+        mount_service(router, &static_dir.prefix, service)
+    }
+}
@@ -0,0 +1,124 @@
+use axum::http::HeaderMap;
+use deno_core::error::AnyError;
+use futures::future::BoxFuture;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Config, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+// Writes finished spans to a file, one JSON object per line, as a stand-in
+// for a collector when testing or debugging locally.
+#[derive(Debug)]
+struct FileExporter {
+    file: File,
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    return time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+}
+
+fn span_json(span: &SpanData) -> Value {
+    let attributes = span
+        .attributes
+        .iter()
+        .map(|kv| (String::from(kv.key.as_str()), json!(kv.value.to_string())))
+        .collect::<Map<_, _>>();
+    return json!({
+        "traceId": span.span_context.trace_id().to_string(),
+        "spanId": span.span_context.span_id().to_string(),
+        "parentSpanId": span.parent_span_id.to_string(),
+        "name": span.name,
+        "kind": format!("{:?}", span.span_kind),
+        "startTimeUnixNano": unix_nanos(span.start_time),
+        "endTimeUnixNano": unix_nanos(span.end_time),
+        "attributes": attributes,
+    });
+}
+
+impl SpanExporter for FileExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let mut lines = String::new();
+        for span in &batch {
+            lines.push_str(&span_json(span).to_string());
+            lines.push('\n');
+        }
+        let res = self
+            .file
+            .write_all(lines.as_bytes())
+            .map_err(|e| TraceError::from(e.to_string()));
+        return Box::pin(std::future::ready(res));
+    }
+}
+
+// Spans are exported when `OTEL_EXPORTER_OTLP_ENDPOINT` (OTLP over gRPC)
+// or `OTEL_TRACES_FILE` is set; without either, no tracer is installed and
+// the spans only serve the log lines.
+pub fn init_tracer() -> Result<Option<Tracer>, AnyError> {
+    let otlp_endpoint = env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok();
+    let traces_file = env::var("OTEL_TRACES_FILE").ok();
+    if otlp_endpoint.is_none() && traces_file.is_none() {
+        return Ok(None);
+    }
+    let service_name =
+        env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| String::from("axum_script"));
+    let mut builder = TracerProvider::builder().with_config(Config::default().with_resource(
+        Resource::new(vec![KeyValue::new("service.name", service_name)]),
+    ));
+    if otlp_endpoint.is_some() {
+        // the endpoint and headers are read from the OTEL_EXPORTER_OTLP_* variables
+        let exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .build_span_exporter()?;
+        builder = builder.with_batch_exporter(exporter, runtime::Tokio);
+    }
+    if let Some(path) = traces_file {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        builder = builder.with_simple_exporter(FileExporter { file });
+    }
+    let provider = builder.build();
+    let tracer = provider.tracer("axum_script");
+    global::set_tracer_provider(provider);
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    return Ok(Some(tracer));
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        return self.0.get(key)?.to_str().ok();
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        return self.0.keys().map(|name| name.as_str()).collect();
+    }
+}
+
+// the caller's trace from a W3C `traceparent` header
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    return global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    });
+}
+
+// `traceparent` (and `tracestate`) for an outgoing request made in `span`
+pub fn trace_headers(span: &Span) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    let context = span.context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut headers));
+    return headers;
+}
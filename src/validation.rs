@@ -0,0 +1,169 @@
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use deno_core::anyhow::anyhow;
+use deno_core::error::AnyError;
+use jsonschema::JSONSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// `route(path, { schema: { params, query, body, response } }, handler)`
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RouteSchema {
+    pub params: Option<Value>,
+    pub query: Option<Value>,
+    pub body: Option<Value>,
+    pub response: Option<Value>,
+}
+
+#[derive(Serialize)]
+pub struct Violation {
+    location: &'static str,
+    path: String,
+    message: String,
+}
+
+pub fn compile_schema(route: &str, schema: &Value) -> Result<JSONSchema, AnyError> {
+    return JSONSchema::compile(schema)
+        .map_err(|e| anyhow!("invalid schema for route {}: {}", route, e));
+}
+
+pub fn validate(schema: &JSONSchema, location: &'static str, instance: &Value) -> Vec<Violation> {
+    return match schema.validate(instance) {
+        Ok(()) => vec![],
+        Err(errors) => errors
+            .map(|e| Violation {
+                location,
+                path: e.instance_path.to_string(),
+                message: e.to_string(),
+            })
+            .collect(),
+    };
+}
+
+pub fn problem_response(status: StatusCode, detail: &str, violations: Vec<Violation>) -> Response {
+    let body = json!({
+        "type": "about:blank",
+        "title": status.canonical_reason(),
+        "status": status.as_u16(),
+        "detail": detail,
+        "errors": violations,
+    });
+    return (
+        status,
+        [(CONTENT_TYPE, "application/problem+json")],
+        Json(body),
+    )
+        .into_response();
+}
+
+// Path and query values always arrive as strings, so they are converted to
+// the number or boolean type their schema property asks for first.
+fn coerce_strings(schema: &Value, values: &mut serde_json::Map<String, Value>) {
+    for (name, value) in values.iter_mut() {
+        let Some(text) = value.as_str() else {
+            continue;
+        };
+        let coerced = match schema.pointer(&format!("/properties/{}/type", name)) {
+            Some(Value::String(t)) if t == "integer" => text.parse::<i64>().ok().map(Value::from),
+            Some(Value::String(t)) if t == "number" => text.parse::<f64>().ok().map(Value::from),
+            Some(Value::String(t)) if t == "boolean" => text.parse::<bool>().ok().map(Value::from),
+            _ => None,
+        };
+        if let Some(coerced) = coerced {
+            *value = coerced;
+        }
+    }
+}
+
+struct CompiledSchema {
+    raw: Value,
+    compiled: JSONSchema,
+}
+
+// the request side of a route schema, checked before the handler is called
+pub struct RequestValidator {
+    params: Option<CompiledSchema>,
+    query: Option<CompiledSchema>,
+    body: Option<CompiledSchema>,
+}
+
+impl RequestValidator {
+    pub fn compile(route: &str, schema: &RouteSchema) -> Result<RequestValidator, AnyError> {
+        let compile = |schema: &Option<Value>| -> Result<Option<CompiledSchema>, AnyError> {
+            match schema {
+                Some(raw) => Ok(Some(CompiledSchema {
+                    raw: raw.clone(),
+                    compiled: compile_schema(route, raw)?,
+                })),
+                None => Ok(None),
+            }
+        };
+        return Ok(RequestValidator {
+            params: compile(&schema.params)?,
+            query: compile(&schema.query)?,
+            body: compile(&schema.body)?,
+        });
+    }
+
+    // Invalid params or query give a 400, a body that does not match its
+    // schema a 422, both listing every violation.
+    pub fn check(
+        &self,
+        params: &mut serde_json::Map<String, Value>,
+        query: &mut serde_json::Map<String, Value>,
+        body: Option<&Value>,
+    ) -> Result<(), Response> {
+        let mut violations = vec![];
+        if let Some(schema) = &self.params {
+            coerce_strings(&schema.raw, params);
+            let instance = Value::Object(params.clone());
+            violations.extend(validate(&schema.compiled, "params", &instance));
+        }
+        if let Some(schema) = &self.query {
+            coerce_strings(&schema.raw, query);
+            let instance = Value::Object(query.clone());
+            violations.extend(validate(&schema.compiled, "query", &instance));
+        }
+        if !violations.is_empty() {
+            return Err(problem_response(
+                StatusCode::BAD_REQUEST,
+                "request validation failed",
+                violations,
+            ));
+        }
+
+        if let Some(schema) = &self.body {
+            let instance = match body {
+                None => Value::Null,
+                Some(body) => match body.get("form") {
+                    Some(form) if form.is_object() => form.clone(),
+                    _ => {
+                        let text = body.get("text").and_then(|t| t.as_str()).unwrap_or("");
+                        match serde_json::from_str(text) {
+                            Ok(value) => value,
+                            Err(e) => {
+                                return Err(problem_response(
+                                    StatusCode::BAD_REQUEST,
+                                    &format!("request body is not valid JSON: {}", e),
+                                    vec![],
+                                ));
+                            }
+                        }
+                    }
+                },
+            };
+            let violations = validate(&schema.compiled, "body", &instance);
+            if !violations.is_empty() {
+                return Err(problem_response(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "request body validation failed",
+                    violations,
+                ));
+            }
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,7 @@
+const admin = router();
+
+admin.route("/users/:id?", async ({ params: { id } }) => {
+  return { json: { id: id ?? null } };
+});
+
+export default admin;
@@ -0,0 +1,12 @@
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use tower::ServiceExt;
+
+pub async fn get(app: &Router, path: &str) -> (StatusCode, String) {
+    let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+    let resp = app.clone().oneshot(req).await.unwrap();
+    let status = resp.status();
+    let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    return (status, String::from_utf8(body.to_vec()).unwrap());
+}
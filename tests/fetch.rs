@@ -0,0 +1,112 @@
+mod common;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header::{CONTENT_TYPE, LOCATION};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use axum_script::ScriptApp;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::time::{sleep, Duration};
+
+// The upstream the fixture fetches from, on its own port in this process.
+async fn stand_in() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let echo = |req: Request| async move {
+        let method = req.method().to_string();
+        let content_type = req.headers().get(CONTENT_TYPE).cloned();
+        let body = axum::body::to_bytes(req.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let text = format!("{} {}", method, String::from_utf8_lossy(&body));
+        match content_type {
+            Some(content_type) => ([(CONTENT_TYPE, content_type)], text).into_response(),
+            None => text.into_response(),
+        }
+    };
+    let redirect = move |to: String| {
+        return move || {
+            let to = to.clone();
+            async move { (StatusCode::FOUND, [(LOCATION, to)]).into_response() }
+        };
+    };
+    let upstream = Router::new()
+        .route("/json", get(|| async { axum::Json(json!({ "mynum": 1 })) }))
+        .route("/echo", post(echo).put(echo))
+        .route(
+            "/stream",
+            get(|| async {
+                let chunks = (0..3).map(|i| Ok::<_, std::io::Error>(format!("chunk {}\n", i)));
+                Response::new(Body::from_stream(futures::stream::iter(chunks)))
+            }),
+        )
+        .route(
+            "/sleep",
+            get(|| async {
+                sleep(Duration::from_secs(1)).await;
+                "slept"
+            }),
+        )
+        .route("/redirect/allowed", get(redirect(String::from("/json"))))
+        .route(
+            "/redirect/denied",
+            get(redirect(format!("http://localhost:{}/json", addr.port()))),
+        );
+    tokio::spawn(async move { axum::serve(listener, upstream).await.unwrap() });
+    return addr;
+}
+
+async fn fetch_app() -> (Router, String) {
+    let upstream = format!("http://{}", stand_in().await);
+    let app = ScriptApp::builder()
+        .entry("tests/fixtures/fetch.js")
+        .build()
+        .await
+        .unwrap();
+    return (app, upstream);
+}
+
+#[tokio::test]
+async fn fetches_from_an_upstream() {
+    let (app, upstream) = fetch_app().await;
+    let (status, body) = common::get(&app, &format!("/fetch?upstream={}", upstream)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let res: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(res["json"], json!({ "mynum": 1 }));
+    assert_eq!(res["echoed"]["status"], 200);
+    assert_eq!(res["echoed"]["type"], "application/json");
+    assert_eq!(res["echoed"]["body"], r#"POST {"via":"fetch"}"#);
+    assert_eq!(res["streamed"], "PUT streamed body");
+    assert!(res["chunks"].as_u64().unwrap() >= 1);
+    assert!(res["denied"].as_str().unwrap().contains("not allowed"));
+    assert_eq!(res["aborted"], "TimeoutError");
+}
+
+#[tokio::test]
+async fn checks_every_redirect_against_the_allow_list() {
+    let (app, upstream) = fetch_app().await;
+    let (status, body) = common::get(&app, &format!("/fetch-redirect?upstream={}", upstream)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let res: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(res["url"], format!("{}/json", upstream));
+    assert_eq!(res["body"], r#"{"mynum":1}"#);
+    let denied = res["denied"].as_str().unwrap();
+    assert!(
+        denied.contains("redirect to localhost is not allowed"),
+        "{}",
+        denied
+    );
+}
+
+#[tokio::test]
+async fn streams_an_upstream_response() {
+    let (app, upstream) = fetch_app().await;
+    let (status, body) = common::get(&app, &format!("/fetch-proxy?upstream={}", upstream)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "chunk 0\nchunk 1\nchunk 2\n");
+}
@@ -0,0 +1 @@
+configure({ imports: { late: "./hello.js" } });
@@ -0,0 +1,67 @@
+// `upstream` is the stand-in server started by tests/fetch.rs. Redirects to
+// "localhost" leave the allow list, which only has the IP.
+configure({ fetch: { allow: ["127.0.0.1"], timeout: 5000 } });
+
+route("/fetch", async ({ query: { upstream } }) => {
+  const json = await (await fetch(`${upstream}/json`)).json();
+
+  const echoed = await fetch(`${upstream}/echo`, {
+    method: "POST",
+    headers: { "content-type": "application/json" },
+    body: JSON.stringify({ via: "fetch" }),
+  });
+
+  const streamed = await fetch(`${upstream}/echo`, {
+    method: "PUT",
+    body: (async function* () {
+      yield "streamed ";
+      yield Deno.core.encode("body");
+    })(),
+  });
+
+  const chunks = [];
+  for await (const chunk of (await fetch(`${upstream}/stream`)).body) {
+    chunks.push(chunk.byteLength);
+  }
+
+  let denied = "";
+  try {
+    await fetch("http://example.invalid/");
+  } catch (e) {
+    denied = e.message;
+  }
+
+  let aborted = "";
+  try {
+    await fetch(`${upstream}/sleep`, { signal: AbortSignal.timeout(10) });
+  } catch (e) {
+    aborted = e.name;
+  }
+
+  return {
+    json: {
+      json,
+      echoed: { status: echoed.status, type: echoed.headers.get("content-type"), body: await echoed.text() },
+      streamed: await streamed.text(),
+      chunks: chunks.length,
+      denied,
+      aborted,
+    },
+  };
+});
+
+route("/fetch-redirect", async ({ query: { upstream } }) => {
+  const followed = await fetch(`${upstream}/redirect/allowed`);
+  let denied = "";
+  try {
+    await fetch(`${upstream}/redirect/denied`);
+  } catch (e) {
+    denied = e.message;
+  }
+  return { json: { url: followed.url, body: await followed.text(), denied } };
+});
+
+route("/fetch-proxy", async ({ query: { upstream } }) => {
+  const resp = await fetch(`${upstream}/stream`);
+  return { stream: resp.body, headers: { "x-upstream-status": String(resp.status) } };
+});
@@ -0,0 +1,3 @@
+route("/hello/:name", async ({ params: { name } }) => {
+  return `hello ${name}`;
+});
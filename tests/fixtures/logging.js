@@ -0,0 +1,22 @@
+await connectToDatabase("sqlite://target/logging-test.db");
+
+route("/log/:name", async (req) => {
+  const { name } = req.params;
+  console.debug(`debug ${name}`);
+  console.info(`info ${name}`);
+  // both requests are in flight by the time this one wakes up
+  await sleep(50);
+  console.warn(`warn ${name}`);
+  req.log.info(`request ${name}`);
+  return "logged";
+});
+
+route("/pending-query", async () => {
+  const pending = query(
+    "with recursive n(x) as (select 1 union all select x + 1 from n where x < 100000) select count(*) as rows from n"
+  );
+  console.info("query pending");
+  setTimeout(() => console.info("timer fired"), 0);
+  const [{ rows }] = await pending;
+  return { json: { rows } };
+});
@@ -0,0 +1,40 @@
+await connectToDatabase("sqlite://target/metrics-test.db");
+
+configure({ metrics: { path: "/metrics" } });
+
+const hits = metrics.counter("fixture_hits_total");
+const sizes = metrics.histogram("fixture_sizes");
+
+route("/bad-values", async () => {
+  const errors = [];
+  for (const record of [() => hits.inc(-1), () => hits.inc(Infinity), () => sizes.observe(NaN)]) {
+    try {
+      record();
+    } catch (e) {
+      errors.push(e.message);
+    }
+  }
+  hits.inc(2);
+  return { json: errors };
+});
+
+// the same definition again is fine, a different one is refused
+route("/redefined", async () => {
+  const errors = [];
+  const definitions = [
+    () => metrics.counter("fixture_hits_total"),
+    () => metrics.histogram("fixture_sizes", { buckets: null }),
+    () => metrics.counter("fixture_hits_total", { labels: ["route"] }),
+    () => metrics.histogram("fixture_sizes", { buckets: [1, 2] }),
+  ];
+  for (const define of definitions) {
+    try {
+      define();
+    } catch (e) {
+      errors.push(e.message);
+    }
+  }
+  return { json: errors };
+});
+
+notFound(async () => ({ status: 404, html: "nothing here" }));
@@ -0,0 +1,11 @@
+// The builder always fails, so the cache can only come from the snapshot.
+await createCache(
+  async () => {
+    throw new Error("the persisted snapshot should have been used");
+  },
+  { persist: "target/persisted-cache-test.json" }
+);
+
+route("/cache", async () => {
+  return { json: getCache() };
+});
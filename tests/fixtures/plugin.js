@@ -0,0 +1,3 @@
+route("/greet/:name", async ({ params: { name } }) => {
+  return greeting(name);
+});
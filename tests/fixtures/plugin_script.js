@@ -0,0 +1,3 @@
+((globalThis) => {
+  globalThis.greeting = (name) => Deno.core.ops.op_test_greeting(name);
+})(globalThis);
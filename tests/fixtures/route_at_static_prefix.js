@@ -0,0 +1,5 @@
+staticDir("/assets", "tests/public");
+
+route("/assets", async () => {
+  return "shadowed by the static directory";
+});
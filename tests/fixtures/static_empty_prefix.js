@@ -0,0 +1 @@
+staticDir("", "tests/public");
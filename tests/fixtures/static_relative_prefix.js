@@ -0,0 +1 @@
+staticDir("assets", "tests/public");
@@ -0,0 +1,5 @@
+staticDir("/", "tests/public");
+
+notFound(async () => {
+  return { json: { error: "not found" }, status: 404 };
+});
@@ -0,0 +1,5 @@
+import { double } from "./transpiled.ts";
+
+route("/double", async () => {
+  return { json: double(21) };
+});
@@ -0,0 +1,14 @@
+let errors = 0;
+
+websocket("/ws", (socket) => {
+  socket.onmessage = ({ data }) => socket.send(data);
+});
+
+onError(async (err) => {
+  errors += 1;
+  return problem({ title: "Internal Server Error", detail: err.message });
+});
+
+route("/errors", async () => {
+  return { json: { errors } };
+});
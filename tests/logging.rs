@@ -0,0 +1,184 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum_script::{init_logging_with_writer, ScriptApp};
+use common::get;
+use serde_json::Value;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, Duration};
+use tower::ServiceExt;
+use tracing_subscriber::fmt::MakeWriter;
+
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl<'a> MakeWriter<'a> for Captured {
+    type Writer = Captured;
+
+    fn make_writer(&'a self) -> Captured {
+        return self.clone();
+    }
+}
+
+impl Captured {
+    fn output(&self) -> String {
+        return String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+    }
+
+    fn lines(&self) -> Vec<Value> {
+        return self
+            .output()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect();
+    }
+
+    fn line(&self, message: &str) -> Value {
+        let line = self
+            .lines()
+            .into_iter()
+            .find(|line| line["message"] == message);
+        return line.unwrap_or_else(|| panic!("no line {:?} in\n{}", message, self.output()));
+    }
+}
+
+// the subscriber is process-wide, so the tests in this file share one
+fn captured() -> Captured {
+    static CAPTURED: OnceLock<Captured> = OnceLock::new();
+    let captured = CAPTURED.get_or_init(|| {
+        std::env::set_var("LOG_FORMAT", "json");
+        std::env::set_var("RUST_LOG", "debug");
+        let captured = Captured::default();
+        init_logging_with_writer(captured.clone()).unwrap();
+        captured
+    });
+    return captured.clone();
+}
+
+#[tokio::test]
+async fn logs_json_with_levels_and_the_request_of_each_line() {
+    let captured = captured();
+    let app = ScriptApp::builder()
+        .entry("tests/fixtures/logging.js")
+        .build()
+        .await
+        .unwrap();
+    // both requests wait on the same worker's event loop at the same time
+    let [first, second] = ["first", "second"].map(|name| {
+        let req = Request::builder()
+            .uri(format!("/log/{}", name))
+            .header("x-request-id", format!("id-{}", name))
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(req)
+    });
+    let (first, second) = tokio::join!(first, second);
+    assert_eq!(first.unwrap().status(), StatusCode::OK);
+    assert_eq!(second.unwrap().status(), StatusCode::OK);
+
+    for name in ["first", "second"] {
+        let lines = [
+            ("DEBUG", format!("debug {}", name)),
+            ("INFO", format!("info {}", name)),
+            // logged after the other request ran on the same event loop
+            ("WARN", format!("warn {}", name)),
+            ("INFO", format!("request {}", name)),
+        ];
+        for (level, message) in lines {
+            let line = captured.line(&message);
+            assert_eq!(line["level"], level);
+            assert_eq!(line["target"], "js");
+            assert_eq!(line["span"]["request_id"], format!("id-{}", name));
+            assert_eq!(line["span"]["method"], "GET");
+            assert_eq!(line["span"]["route"], "/log/:name");
+        }
+    }
+}
+
+#[tokio::test]
+async fn logs_and_sets_timers_while_a_query_is_pending() {
+    let captured = captured();
+    let app = ScriptApp::builder()
+        .entry("tests/fixtures/logging.js")
+        .build()
+        .await
+        .unwrap();
+    let req = Request::builder()
+        .uri("/pending-query")
+        .header("x-request-id", "id-pending")
+        .body(Body::empty())
+        .unwrap();
+    let resp = app.oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    for message in ["query pending", "timer fired"] {
+        let line = captured.line(message);
+        assert_eq!(line["span"]["request_id"], "id-pending");
+        assert_eq!(line["span"]["route"], "/pending-query");
+    }
+}
+
+#[tokio::test]
+async fn websocket_upgrades_are_not_rendered_as_responses() {
+    let captured = captured();
+    let app = ScriptApp::builder()
+        .entry("tests/fixtures/websocket.js")
+        .workers(1)
+        .build()
+        .await
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = app.clone();
+    tokio::spawn(async move { axum::serve(listener, server).await });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET /ws HTTP/1.1\r\n\
+              Host: localhost\r\n\
+              Connection: Upgrade\r\n\
+              Upgrade: websocket\r\n\
+              Sec-WebSocket-Version: 13\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    let handshake = String::from_utf8_lossy(&buf[..n]);
+    assert!(handshake.starts_with("HTTP/1.1 101"), "{}", handshake);
+
+    // a masked "hi" text frame, echoed back unmasked once the handler runs
+    stream
+        .write_all(&[0x81, 0x82, 0, 0, 0, 0, b'h', b'i'])
+        .await
+        .unwrap();
+    let mut echo = [0; 4];
+    stream.read_exact(&mut echo).await.unwrap();
+    assert_eq!(echo, [0x81, 0x02, b'h', b'i']);
+    sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(get(&app, "/errors").await.1, r#"{"errors":0}"#);
+    let errors: Vec<Value> = captured
+        .lines()
+        .into_iter()
+        .filter(|line| line["level"] == "ERROR")
+        .collect();
+    assert!(errors.is_empty(), "{:?}", errors);
+}
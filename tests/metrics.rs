@@ -0,0 +1,96 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_script::ScriptApp;
+use serde_json::Value;
+
+fn metric_lines<'a>(body: &'a str, name: &str) -> Vec<&'a str> {
+    let prefix = format!("{}{{", name);
+    return body
+        .lines()
+        .filter(|line| line.starts_with(&prefix))
+        .collect();
+}
+
+#[tokio::test]
+async fn exports_checked_values_the_fallback_and_the_pool() {
+    let app = ScriptApp::builder()
+        .entry("tests/fixtures/metrics.js")
+        .build()
+        .await
+        .unwrap();
+
+    let (status, body) = common::get(&app, "/bad-values").await;
+    assert_eq!(status, StatusCode::OK);
+    let errors: Value = serde_json::from_str(&body).unwrap();
+    let errors = errors.as_array().unwrap();
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(errors[0]
+        .as_str()
+        .unwrap()
+        .contains("can't be increased by -1"));
+    assert!(errors[1]
+        .as_str()
+        .unwrap()
+        .contains("can't be increased by inf"));
+    assert!(errors[2].as_str().unwrap().contains("can't observe NaN"));
+
+    let (status, _) = common::get(&app, "/no/such/page").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = common::get(&app, "/metrics").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        body.lines().any(|line| line == "fixture_hits_total 2"),
+        "{}",
+        body
+    );
+    let not_found = metric_lines(&body, "http_requests_total");
+    assert!(
+        not_found
+            .iter()
+            .any(|line| line.contains(r#"route="""#) && line.contains(r#"status="404""#)),
+        "{}",
+        body
+    );
+    // no query ran, the gauges are read when the metrics are
+    let connections = metric_lines(&body, "db_pool_connections");
+    assert!(
+        connections
+            .iter()
+            .any(|line| line.contains(r#"worker="js-worker-0""#)),
+        "{}",
+        body
+    );
+    // the discovery runtime is gone, and its pool with it
+    assert!(
+        !connections
+            .iter()
+            .any(|line| line.contains(r#"worker="js-setup""#)),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test]
+async fn refuses_to_redefine_a_metric_with_other_labels_or_buckets() {
+    let app = ScriptApp::builder()
+        .entry("tests/fixtures/metrics.js")
+        .build()
+        .await
+        .unwrap();
+
+    let (status, body) = common::get(&app, "/redefined").await;
+    assert_eq!(status, StatusCode::OK);
+    let errors: Value = serde_json::from_str(&body).unwrap();
+    let errors = errors.as_array().unwrap();
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors[0]
+        .as_str()
+        .unwrap()
+        .contains("fixture_hits_total is already registered with the labels []"));
+    assert!(errors[1]
+        .as_str()
+        .unwrap()
+        .contains("fixture_sizes is already registered with the buckets"));
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_script::ScriptApp;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs;
+
+#[tokio::test]
+async fn starts_from_the_persisted_snapshot() {
+    let data = json!({ "names": ["Ada", "Grace"] });
+    let snapshot = json!({
+        "version": 1,
+        "generation": 7,
+        "hash": format!("{:x}", Sha256::digest(data.to_string().as_bytes())),
+        "data": data,
+    });
+    fs::create_dir_all("target").unwrap();
+    fs::write("target/persisted-cache-test.json", snapshot.to_string()).unwrap();
+
+    let app = ScriptApp::builder()
+        .entry("tests/fixtures/persisted_cache.js")
+        .build()
+        .await
+        .unwrap();
+    let (status, body) = common::get(&app, "/cache").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), data);
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_script::deno_core::{self, op2, Extension, OpState};
+use axum_script::{Plugin, ScriptApp};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+struct Salutation(String);
+
+// names the worker, so the test can tell which runtime answered
+#[op2]
+#[string]
+fn op_test_greeting(state: &mut OpState, #[string] name: String) -> String {
+    let salutation = state.borrow::<Salutation>();
+    let worker = thread::current()
+        .name()
+        .map(String::from)
+        .unwrap_or_default();
+    return format!("{}, {}! ({})", salutation.0, name, worker);
+}
+
+deno_core::extension!(
+    test_greeting_extension,
+    ops = [op_test_greeting],
+    js = ["tests/fixtures/plugin_script.js"]
+);
+
+struct Greeting {
+    salutation: String,
+    // the threads `init_state` ran on
+    initialized: Arc<Mutex<Vec<String>>>,
+}
+
+impl Plugin for Greeting {
+    fn extension(&self) -> Extension {
+        return test_greeting_extension::init_ops_and_esm();
+    }
+
+    fn init_state(&self, state: &mut OpState) {
+        let thread = thread::current()
+            .name()
+            .map(String::from)
+            .unwrap_or_default();
+        self.initialized.lock().unwrap().push(thread);
+        state.put(Salutation(self.salutation.clone()));
+    }
+}
+
+#[tokio::test]
+async fn runs_the_plugin_on_every_worker() {
+    let initialized = Arc::new(Mutex::new(vec![]));
+    let app = ScriptApp::builder()
+        .entry("tests/fixtures/plugin.js")
+        .workers(2)
+        .plugin(Greeting {
+            salutation: String::from("Hello"),
+            initialized: Arc::clone(&initialized),
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let mut threads = initialized.lock().unwrap().clone();
+    threads.sort();
+    assert_eq!(threads, ["js-setup", "js-worker-0", "js-worker-1"]);
+
+    // requests go round-robin, so both workers answer
+    let mut answers = BTreeSet::new();
+    for _ in 0..2 {
+        let (status, body) = common::get(&app, "/greet/ada").await;
+        assert_eq!(status, StatusCode::OK);
+        answers.insert(body);
+    }
+    assert_eq!(
+        answers,
+        BTreeSet::from([
+            String::from("Hello, ada! (js-worker-0)"),
+            String::from("Hello, ada! (js-worker-1)"),
+        ])
+    );
+}
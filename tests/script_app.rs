@@ -0,0 +1,80 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use axum_script::deno_core::{Extension, OpState};
+use axum_script::{Plugin, ScriptApp};
+use std::thread;
+
+#[tokio::test]
+async fn serves_routes_next_to_native_ones() {
+    let scripts = ScriptApp::builder()
+        .entry("tests/fixtures/hello.js")
+        .workers(2)
+        .build()
+        .await
+        .unwrap();
+    let app = Router::new()
+        .route("/", get(|| async { "native axum route" }))
+        .nest("/scripts", scripts);
+
+    assert_eq!(
+        common::get(&app, "/").await,
+        (StatusCode::OK, String::from("native axum route"))
+    );
+    // one request per worker
+    for _ in 0..2 {
+        assert_eq!(
+            common::get(&app, "/scripts/hello/ada").await,
+            (StatusCode::OK, String::from("hello ada"))
+        );
+    }
+}
+
+#[tokio::test]
+async fn fails_to_build_when_the_entry_does_not_load() {
+    let res = ScriptApp::builder()
+        .entry("tests/fixtures/missing.js")
+        .build()
+        .await;
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn refuses_imports_configured_by_the_setup_file() {
+    let res = ScriptApp::builder()
+        .entry("tests/fixtures/configure_imports.js")
+        .build()
+        .await;
+    let e = format!("{:#}", res.err().expect("the app should not build"));
+    assert!(e.contains("axum_script.json"), "{}", e);
+}
+
+// loads fine for route discovery, but not on the workers
+struct BrokenOnWorkers;
+
+impl Plugin for BrokenOnWorkers {
+    fn extension(&self) -> Extension {
+        return Extension::default();
+    }
+
+    fn init_state(&self, _state: &mut OpState) {
+        let name = thread::current().name().map(String::from);
+        if name.is_some_and(|name| name.starts_with("js-worker-")) {
+            panic!("no worker state");
+        }
+    }
+}
+
+#[tokio::test]
+async fn fails_to_build_when_a_worker_does_not_start() {
+    let res = ScriptApp::builder()
+        .entry("tests/fixtures/hello.js")
+        .workers(2)
+        .plugin(BrokenOnWorkers)
+        .build()
+        .await;
+    let e = format!("{:#}", res.err().expect("the app should not build"));
+    assert!(e.contains("stopped while starting"), "{}", e);
+}
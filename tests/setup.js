@@ -1,4 +1,10 @@
 import {} from "./other.js";
+import admin from "./admin.js";
+import { failTyped, Greeting, h } from "./views.tsx";
+import { greet } from "greet";
+import { greet as greetConfigured } from "configured";
+import { banner } from "vendor/legacy/banner.js";
+import data from "./data.json" with { type: "json" };
 
 await connectToDatabase("sqlite://sqlite.db");
 
@@ -8,13 +14,50 @@ await execute(`create table if not exists person (
    age INTEGER
 );`);
 
-await createCache(async () => {
-  console.log("creating cache");
-  const name_rows = await query("select name from person order by id");
-  const c = { akey: 1, bkey: 2, names: name_rows.map((row) => row.name) };
-  console.log("new cache", c);
+configure({
+  bodyLimit: 1024 * 1024,
+  validateResponses: true,
+  openapi: { path: "/openapi.json", title: "axum_script tests" },
+  // the upstream test.js serves for the traced fetches
+  fetch: { allow: ["127.0.0.1:4002"], timeout: 5000 },
+});
+
+await createCache(
+  async () => {
+    console.log("creating cache");
+    const name_rows = await query("select name from person order by id");
+    const c = { akey: 1, bkey: 2, names: name_rows.map((row) => row.name) };
+    console.log("new cache", c);
+
+    return c;
+  },
+  { persist: "datacache.json", stats: "/__admin/cache" }
+);
 
-  return c;
+use(async (req, next) => {
+  const res = await next();
+  if (res && typeof res === "object") {
+    res.headers = { ...res.headers, "x-powered-by": "axum_script" };
+  }
+  return res;
+});
+
+use("/admin", async (req, next) => {
+  if (req.headers["authorization"] !== "Bearer secret") {
+    return { json: { error: "unauthorized" }, status: 401 };
+  }
+  return next();
+});
+
+route("/admin/panel", async ({ method, query }) => {
+  return { json: { method, tab: query.tab } };
+});
+
+staticDir("/assets", "tests/public", { maxAge: 60 });
+staticDir("/app", "tests/public/app", { spa: true });
+
+route("/assets/dynamic", async () => {
+  return "dynamic wins";
 });
 
 route("/db-txt", async () => {
@@ -42,10 +85,255 @@ route("/get-cache", async () => {
   };
 });
 
+route("/cache-version", { cacheEtag: true }, async () => {
+  return { json: cacheVersion() };
+});
+
+let cacheChanges = 0;
+onCacheChange(() => {
+  cacheChanges += 1;
+});
+
+route("/cache-changes", async () => {
+  return { json: { count: cacheChanges } };
+});
+
+route("/cache-events", async () => {
+  return { cacheEvents: { diff: true } };
+});
+
+route("/update-cache", async () => {
+  updateCache("ckey", 3);
+  patchCache([
+    { op: "test", path: "/akey", value: 1 },
+    { op: "add", path: "/dkey", value: { nested: [4] } },
+  ]);
+  let failed = false;
+  try {
+    patchCache([{ op: "remove", path: "/missing" }]);
+  } catch (_e) {
+    failed = true;
+  }
+  return { json: { failed, cache: getCache(["ckey", "dkey"]) } };
+});
+
+route("/update-in-with-cache", async () => {
+  const ckey = getCache((cache) => {
+    updateCache("ekey", cache.ckey);
+    return cache.ckey;
+  });
+  return { json: { ckey, ekey: getCache("ekey") } };
+});
+
+route("/cache-stats", async () => {
+  return { json: cacheStats() };
+});
+
+websocket("/ws/:room", (socket, { params: { room } }) => {
+  socket.onmessage = ({ data }) =>
+    socket.send(typeof data === "string" ? `${room}: ${data}` : data);
+});
+
+let lastServerClose = null;
+
+websocket("/ws-close", (socket) => {
+  socket.onmessage = () => socket.close(4001, "bye");
+  socket.onclose = (event) => {
+    lastServerClose = event;
+  };
+});
+
+route("/ws-close/last", async () => {
+  return { json: lastServerClose };
+});
+
+route("/stream", async () => {
+  return (async function* () {
+    for (let i = 0; i < 3; i++) {
+      await sleep(10);
+      yield `chunk ${i}\n`;
+    }
+  })();
+});
+
+route("/empty-stream", async () => {
+  return (async function* () {})();
+});
+
+let unsentStreamStopped = false;
+
+// fails after the stream was created, so it never becomes the response
+route("/unsent-stream", async () => {
+  const chunks = (async function* () {
+    try {
+      for (let i = 0; ; i++) yield `chunk ${i}\n`;
+    } finally {
+      unsentStreamStopped = true;
+    }
+  })();
+  return {
+    stream: chunks,
+    get status() {
+      throw new Error("no status");
+    },
+  };
+});
+
+route("/unsent-stream/stopped", async () => {
+  return { json: unsentStreamStopped };
+});
+
+route("/sse", async () => {
+  return sse(
+    (async function* () {
+      yield "hello";
+      yield { event: "tick", id: 2, data: { n: 2 } };
+    })()
+  );
+});
+
+notFound(async ({ path }) => {
+  return problem({ status: 404, title: "Not Found", detail: `${path} does not exist` });
+});
+
+onError(async (err, { path }) => {
+  return problem({ title: "Internal Server Error", detail: err.message, instance: path });
+});
+
+route("/fail", async () => {
+  throw new Error("handler exploded");
+});
+
+route("/files/*path", async ({ params: { path } }) => {
+  return `file ${path}`;
+});
+
+group("/api/v1", () => {
+  route("/ping", async () => "pong");
+  group("/sub", admin);
+});
+
 route("/baz/:id", async ({ params: { id } }) => {
   return `hello from the baz with arg ${id}`;
 });
 
+let duplicateError = "";
+try {
+  route("/baz/:id", async () => "duplicate");
+} catch (e) {
+  duplicateError = String(e);
+}
+route("/duplicate-error", async () => duplicateError);
+
+route("/echo", { method: ["POST", "PUT"] }, async (req) => {
+  const type = req.headers["content-type"] ?? "";
+  if (type.startsWith("application/json")) {
+    return { json: { method: req.method, json: await req.json() } };
+  }
+  if (type.startsWith("application/x-www-form-urlencoded")) {
+    return { json: { method: req.method, form: await req.form() } };
+  }
+  return { json: { method: req.method, text: await req.text() } };
+});
+
+route("/echo", async () => "send a POST");
+
+route("/upload", { method: "POST", bodyLimit: 64 * 1024 }, async (req) => {
+  const parts = await req.multipart();
+  const files = [];
+  for (const part of parts.filter((part) => part.filename)) {
+    files.push({ name: part.name, filename: part.filename, size: part.size, text: await part.text() });
+  }
+  return { json: { form: await req.form(), files } };
+});
+
+route("/upload-paths", { method: "POST" }, async (req) => {
+  const parts = await req.multipart();
+  return { json: parts.filter((part) => part.filename).map((part) => part.path) };
+});
+
+const pet = {
+  type: "object",
+  properties: { name: { type: "string", minLength: 1 }, age: { type: "integer" } },
+  required: ["name"],
+};
+
+route(
+  "/pets/:id",
+  {
+    method: "POST",
+    summary: "Store a pet",
+    tags: ["pets"],
+    schema: {
+      params: { type: "object", properties: { id: { type: "integer", minimum: 1 } } },
+      query: { type: "object", properties: { dry: { type: "boolean" } } },
+      body: pet,
+      response: pet,
+    },
+  },
+  async ({ params, query, json }) => {
+    const body = await json();
+    if (query.dry === true) return { json: { name: body.name, age: "unknown" } };
+    return { json: { ...body, age: params.id } };
+  }
+);
+
+const abortReasons = [];
+
+async function waitForAbort({ signal }) {
+  await new Promise((resolve) => signal.addEventListener("abort", resolve));
+  abortReasons.push(signal.reason.name);
+  return "too late";
+}
+
+route("/slow", { timeout: 100 }, waitForAbort);
+route("/hang", waitForAbort);
+route("/abort-reasons", async () => ({ json: abortReasons }));
+
+// an unref'd timer does not keep the setup phase from finishing
+const heartbeat = setInterval(() => {}, 1000);
+Deno.unrefTimer(heartbeat);
+
+route("/timers", async () => {
+  const events = [];
+  queueMicrotask(() => events.push("microtask"));
+  const cleared = setTimeout(() => events.push("cleared"), 10);
+  clearTimeout(cleared);
+  events.push(await new Promise((resolve) => setTimeout(resolve, 20, "timeout")));
+  let ticks = 0;
+  await new Promise((resolve) => {
+    const id = setInterval(() => {
+      ticks += 1;
+      if (ticks === 3) {
+        clearInterval(id);
+        resolve();
+      }
+    }, 5);
+  });
+  events.push(`ticks ${ticks}`);
+  return { json: events };
+});
+
+route("/tsx/:name", async ({ params: { name }, query }) => {
+  return String(h(Greeting, { name, excited: query.excited === "1" }));
+});
+
+route("/ts-stack", async () => {
+  try {
+    failTyped("on purpose");
+  } catch (e) {
+    return { json: { message: e.message, stack: e.stack } };
+  }
+});
+
+route("/import-map", async () => {
+  return { json: { greet: greet("ann"), banner, answer: data.answer } };
+});
+
+route("/configured-import", async () => {
+  return greetConfigured("configured");
+});
+
 route("/insert-name/:name/:age", async ({ params: { name, age } }) => {
   await execute(`insert into person(name, age) values ($1, $2);`, [name, age]);
   await flushCache();
@@ -63,3 +351,26 @@ route("/sleep", async () => {
   await sleep(100);
   return "hello from sleep";
 });
+
+const greetings = metrics.counter("test_greetings_total", {
+  help: "Greetings sent by /greet",
+  labels: ["lang"],
+});
+const greetingLength = metrics.histogram("test_greeting_length", {
+  buckets: [5, 10, 20],
+});
+
+route("/greet/:lang", async ({ params: { lang } }) => {
+  const text = lang === "de" ? "Hallo" : "Hello";
+  greetings.inc({ lang });
+  greetingLength.observe(text.length);
+  return text;
+});
+
+route("/traced", async (req) => {
+  // lets the test's second request start before this one queries
+  await sleep(50);
+  const rows = await req.db.query("select 1 as mynum");
+  const upstream = await req.fetch("http://127.0.0.1:4002/");
+  return { json: { rows, traceparent: await upstream.text() } };
+});
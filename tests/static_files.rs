@@ -0,0 +1,26 @@
+use axum_script::ScriptApp;
+
+async fn build_error(entry: &str) -> String {
+    let res = ScriptApp::builder().entry(entry).build().await;
+    return format!("{:#}", res.err().expect("the app should not build"));
+}
+
+#[tokio::test]
+async fn refuses_a_root_static_dir_with_not_found() {
+    let e = build_error("tests/fixtures/static_root_and_not_found.js").await;
+    assert!(e.contains("notFound()"), "{}", e);
+}
+
+#[tokio::test]
+async fn refuses_a_route_at_a_static_prefix() {
+    let e = build_error("tests/fixtures/route_at_static_prefix.js").await;
+    assert!(e.contains("/assets"), "{}", e);
+}
+
+#[tokio::test]
+async fn refuses_a_static_prefix_without_a_leading_slash() {
+    let e = build_error("tests/fixtures/static_relative_prefix.js").await;
+    assert!(e.contains("must start with \"/\""), "{}", e);
+    let e = build_error("tests/fixtures/static_empty_prefix.js").await;
+    assert!(e.contains("must start with \"/\""), "{}", e);
+}
@@ -43,6 +43,157 @@ Deno.test("Get cache", async () => {
   assertEquals(c.list.akey, 1);
 });
 
+Deno.test("Cache ETag", async () => {
+  const resp = await fetch("http://localhost:4000/cache-version");
+  assertEquals(resp.status, 200);
+  const etag = resp.headers.get("etag");
+  const v = await resp.json();
+  assertEquals(etag, v.etag);
+  assert(v.version >= 1);
+
+  const resp1 = await fetch("http://localhost:4000/cache-version", {
+    headers: { "if-none-match": etag },
+  });
+  assertEquals(resp1.status, 304);
+  await resp1.body?.cancel();
+});
+
+Deno.test("Cache stats", async () => {
+  await fetch("http://localhost:4000/get-cache").then((r) => r.text());
+  const resp = await fetch("http://localhost:4000/__admin/cache");
+  assertEquals(resp.status, 200);
+  const stats = await resp.json();
+  assert(stats.builds >= 1);
+  assertEquals(stats.failures, 0);
+  assert(stats.sizeBytes > 0);
+  assert(stats.keyReads.akey >= 1);
+
+  const fromJs = await (await fetch("http://localhost:4000/cache-stats")).json();
+  assert(fromJs.generation >= stats.generation);
+});
+
+Deno.test("Static files", async () => {
+  const resp = await fetch("http://localhost:4000/assets/hello.txt");
+  assertEquals(resp.status, 200);
+  assertEquals(resp.headers.get("cache-control"), "public, max-age=60");
+  assertEquals(await resp.text(), "hello static\n");
+
+  const dynamic = await fetch("http://localhost:4000/assets/dynamic");
+  assertEquals(await dynamic.text(), "dynamic wins");
+
+  const missing = await fetch("http://localhost:4000/assets/missing.txt");
+  assertEquals(missing.status, 404);
+  await missing.body?.cancel();
+
+  const spa = await fetch("http://localhost:4000/app/some/client/route");
+  assertEquals(spa.status, 200);
+  assert((await spa.text()).includes('<div id="app">'));
+});
+
+Deno.test("WebSocket echo", async () => {
+  const ws = new WebSocket("ws://localhost:4000/ws/lobby");
+  ws.binaryType = "arraybuffer";
+  const messages = [];
+  const received = new Promise((resolve) => {
+    ws.onmessage = ({ data }) => {
+      messages.push(data);
+      if (messages.length === 2) resolve();
+    };
+  });
+  await new Promise((resolve) => (ws.onopen = resolve));
+  ws.send("hi");
+  ws.send(new Uint8Array([1, 2, 3]));
+  await received;
+
+  assertEquals(messages[0], "lobby: hi");
+  assertEquals([...new Uint8Array(messages[1])], [1, 2, 3]);
+
+  const closed = new Promise((resolve) => (ws.onclose = resolve));
+  ws.close();
+  await closed;
+});
+
+Deno.test("WebSocket closed from JS", async () => {
+  const ws = new WebSocket("ws://localhost:4000/ws-close");
+  await new Promise((resolve) => (ws.onopen = resolve));
+  const closed = new Promise((resolve) => (ws.onclose = resolve));
+  ws.send("close please");
+  const event = await closed;
+  assertEquals(event.code, 4001);
+  assertEquals(event.reason, "bye");
+
+  await sleep(50);
+  const last = await (await fetch("http://localhost:4000/ws-close/last")).json();
+  assertEquals(last, { code: 4001, reason: "bye" });
+});
+
+Deno.test("Empty streaming response", async () => {
+  const resp = await fetch("http://localhost:4000/empty-stream");
+  assertEquals(resp.status, 200);
+  assertEquals(await resp.text(), "");
+});
+
+Deno.test("Streaming response", async () => {
+  const resp = await fetch("http://localhost:4000/stream");
+  assertEquals(resp.status, 200);
+  assertEquals(await resp.text(), "chunk 0\nchunk 1\nchunk 2\n");
+});
+
+Deno.test("Streams of failed responses are dropped", async () => {
+  const resp = await fetch("http://localhost:4000/unsent-stream");
+  assertEquals(resp.status, 500);
+  await resp.body?.cancel();
+
+  await sleep(50);
+  const stopped = await fetch("http://localhost:4000/unsent-stream/stopped");
+  assertEquals(await stopped.json(), true);
+});
+
+Deno.test("Server-sent events from JS", async () => {
+  const resp = await fetch("http://localhost:4000/sse");
+  assertEquals(resp.headers.get("content-type"), "text/event-stream");
+  assertEquals(
+    await resp.text(),
+    'data: hello\n\nevent: tick\nid: 2\ndata: {"n":2}\n\n'
+  );
+});
+
+Deno.test("Middleware", async () => {
+  const denied = await fetch("http://localhost:4000/admin/panel");
+  assertEquals(denied.status, 401);
+  assertEquals(denied.headers.get("x-powered-by"), "axum_script");
+  await denied.body?.cancel();
+
+  const resp = await fetch("http://localhost:4000/admin/panel?tab=users", {
+    headers: { authorization: "Bearer secret" },
+  });
+  assertEquals(resp.status, 200);
+  assertEquals(await resp.json(), { method: "GET", tab: "users" });
+
+  const text = await fetch("http://localhost:4000/db-txt");
+  assertEquals(text.headers.get("x-powered-by"), "axum_script");
+  assertEquals(text.headers.get("content-type"), "text/html; charset=utf-8");
+  await text.body?.cancel();
+});
+
+Deno.test("Custom not found handler", async () => {
+  const resp = await fetch("http://localhost:4000/no/such/page");
+  assertEquals(resp.status, 404);
+  assertEquals(resp.headers.get("content-type"), "application/problem+json");
+  const body = await resp.json();
+  assertEquals(body.title, "Not Found");
+  assertEquals(body.detail, "/no/such/page does not exist");
+});
+
+Deno.test("Custom error handler", async () => {
+  const resp = await fetch("http://localhost:4000/fail");
+  assertEquals(resp.status, 500);
+  assertEquals(resp.headers.get("content-type"), "application/problem+json");
+  const body = await resp.json();
+  assertEquals(body.detail, "handler exploded");
+  assertEquals(body.instance, "/fail");
+});
+
 Deno.test("Query string", async () => {
   const resp = await fetch("http://localhost:4000/baz/1");
 
@@ -50,6 +201,181 @@ Deno.test("Query string", async () => {
   assertEquals(txt, "hello from the baz with arg 1");
 });
 
+Deno.test("Wildcard route", async () => {
+  const resp = await fetch("http://localhost:4000/files/docs/readme.md");
+  assertEquals(await resp.text(), "file docs/readme.md");
+});
+
+Deno.test("Route groups and sub-applications", async () => {
+  const ping = await fetch("http://localhost:4000/api/v1/ping");
+  assertEquals(await ping.text(), "pong");
+
+  const one = await fetch("http://localhost:4000/api/v1/sub/users/7");
+  assertEquals(await one.json(), { id: "7" });
+
+  const all = await fetch("http://localhost:4000/api/v1/sub/users");
+  assertEquals(await all.json(), { id: null });
+});
+
+Deno.test("Duplicate route registration", async () => {
+  const resp = await fetch("http://localhost:4000/duplicate-error");
+  assert((await resp.text()).includes("registered more than once"));
+});
+
+Deno.test("Request bodies", async () => {
+  const json = await fetch("http://localhost:4000/echo", {
+    method: "POST",
+    headers: { "content-type": "application/json" },
+    body: JSON.stringify({ a: [1, 2] }),
+  });
+  assertEquals(await json.json(), { method: "POST", json: { a: [1, 2] } });
+
+  const form = await fetch("http://localhost:4000/echo", {
+    method: "PUT",
+    body: new URLSearchParams({ name: "ann", age: "3" }),
+  });
+  assertEquals(await form.json(), { method: "PUT", form: { name: "ann", age: "3" } });
+
+  const get = await fetch("http://localhost:4000/echo");
+  assertEquals(await get.text(), "send a POST");
+
+  const del = await fetch("http://localhost:4000/echo", { method: "DELETE" });
+  assertEquals(del.status, 405);
+  await del.body?.cancel();
+});
+
+Deno.test("Multipart uploads", async () => {
+  const body = new FormData();
+  body.append("title", "notes");
+  body.append("file", new Blob(["hello upload"]), "notes.txt");
+  const resp = await fetch("http://localhost:4000/upload", { method: "POST", body });
+  assertEquals(resp.status, 200);
+  assertEquals(await resp.json(), {
+    form: { title: "notes" },
+    files: [{ name: "file", filename: "notes.txt", size: 12, text: "hello upload" }],
+  });
+
+  const large = new FormData();
+  large.append("file", new Blob(["x".repeat(128 * 1024)]), "large.txt");
+  const tooLarge = await fetch("http://localhost:4000/upload", {
+    method: "POST",
+    body: large,
+  });
+  assertEquals(tooLarge.status, 413);
+  await tooLarge.body?.cancel();
+});
+
+Deno.test("Uploads are removed after the response", async () => {
+  const body = new FormData();
+  body.append("file", new Blob(["temporary"]), "tmp.txt");
+  const resp = await fetch("http://localhost:4000/upload-paths", { method: "POST", body });
+  const [path] = await resp.json();
+  // the files are removed off the request path once the body is sent
+  for (let i = 0; i < 50; i++) {
+    try {
+      await Deno.stat(path);
+    } catch {
+      return;
+    }
+    await sleep(20);
+  }
+  throw new Error(`${path} was not removed`);
+});
+
+Deno.test("Schema validation", async () => {
+  const post = (path, body) =>
+    fetch(`http://localhost:4000${path}`, {
+      method: "POST",
+      headers: { "content-type": "application/json" },
+      body: JSON.stringify(body),
+    });
+
+  const ok = await post("/pets/7", { name: "rex" });
+  assertEquals(ok.status, 200);
+  assertEquals(await ok.json(), { name: "rex", age: 7 });
+
+  const badParams = await post("/pets/0?dry=maybe", { name: "rex" });
+  assertEquals(badParams.status, 400);
+  assertEquals(badParams.headers.get("content-type"), "application/problem+json");
+  const problem = await badParams.json();
+  assertEquals(
+    problem.errors.map((e) => e.location).sort(),
+    ["params", "query"]
+  );
+
+  const badBody = await post("/pets/7", { age: 3 });
+  assertEquals(badBody.status, 422);
+  assertEquals((await badBody.json()).errors[0].location, "body");
+
+  const badResponse = await post("/pets/7?dry=true", { name: "rex" });
+  assertEquals(badResponse.status, 500);
+  assertEquals((await badResponse.json()).errors[0].location, "response");
+});
+
+Deno.test("OpenAPI document", async () => {
+  const resp = await fetch("http://localhost:4000/openapi.json");
+  const doc = await resp.json();
+  assertEquals(doc.openapi, "3.1.0");
+  assertEquals(doc.info.title, "axum_script tests");
+
+  const op = doc.paths["/pets/{id}"].post;
+  assertEquals(op.summary, "Store a pet");
+  assertEquals(op.tags, ["pets"]);
+  assertEquals(
+    op.parameters.map(({ name, in: location }) => `${location}:${name}`),
+    ["path:id", "query:dry"]
+  );
+  assertEquals(op.parameters[0].schema.type, "integer");
+  assertEquals(op.requestBody.content["application/json"].schema.required, ["name"]);
+  assert(doc.paths["/echo"].put);
+  assertEquals(doc.paths["/ws/{room}"], undefined);
+});
+
+Deno.test("Timeouts and disconnects abort the handler", async () => {
+  const slow = await fetch("http://localhost:4000/slow");
+  assertEquals(slow.status, 504);
+  await slow.body?.cancel();
+
+  const controller = new AbortController();
+  const hang = fetch("http://localhost:4000/hang", { signal: controller.signal });
+  await sleep(50);
+  controller.abort();
+  await hang.catch(() => {});
+
+  await sleep(100);
+  const reasons = await (await fetch("http://localhost:4000/abort-reasons")).json();
+  assertEquals(reasons, ["TimeoutError", "AbortError"]);
+});
+
+Deno.test("Timers", async () => {
+  const resp = await fetch("http://localhost:4000/timers");
+  assertEquals(await resp.json(), ["microtask", "timeout", "ticks 3"]);
+});
+
+Deno.test("TypeScript and JSX modules", async () => {
+  const resp = await fetch("http://localhost:4000/tsx/a%3Cb?excited=1");
+  assertEquals(await resp.text(), '<p class="greeting">Hello, a&lt;b!</p>');
+
+  const { message, stack } = await (
+    await fetch("http://localhost:4000/ts-stack")
+  ).json();
+  assertEquals(message, "typed failure: on purpose");
+  // source maps point back to the original TypeScript line
+  assert(stack.includes("views.tsx:65:"), stack);
+});
+
+Deno.test("Import map", async () => {
+  const resp = await fetch("http://localhost:4000/import-map");
+  assertEquals(await resp.json(), {
+    greet: "hello ann",
+    banner: "hi banner (legacy)",
+    answer: 42,
+  });
+
+  const configured = await fetch("http://localhost:4000/configured-import");
+  assertEquals(await configured.text(), "hello configured");
+});
+
 Deno.test("Import", async () => {
   const resp = await fetch("http://localhost:4000/other");
 
@@ -78,3 +404,177 @@ Deno.test("query", async () => {
   const person = await resp.json();
   assertEquals(person.age, 34);
 });
+
+Deno.test("Persisted cache", async () => {
+  const snapshot = JSON.parse(await Deno.readTextFile("datacache.json"));
+  assertEquals(snapshot.version, 1);
+  assertEquals(typeof snapshot.hash, "string");
+  assertEquals(snapshot.data.akey, 1);
+});
+
+Deno.test("Cache change events", async () => {
+  const before = await (
+    await fetch("http://localhost:4000/cache-changes")
+  ).json();
+  // a name no other test inserts, so the expected diff does not depend on
+  // which tests ran before
+  const name = `Events${Date.now()}`;
+  const { all } = await (await fetch("http://localhost:4000/get-cache")).json();
+
+  const events = await fetch("http://localhost:4000/cache-events");
+  assertEquals(events.headers.get("content-type"), "text/event-stream");
+  const reader = events.body.pipeThrough(new TextDecoderStream()).getReader();
+
+  const resp = await fetch(`http://localhost:4000/insert-name/${name}/27`);
+  assertEquals(await resp.text(), "OK");
+
+  let frame = "";
+  while (!frame.includes("\n\n")) {
+    const { value } = await reader.read();
+    frame += value;
+  }
+  await reader.cancel();
+  const data = JSON.parse(
+    frame.split("\n").find((line) => line.startsWith("data:")).slice(5)
+  );
+  assertEquals(data.changed, ["names"]);
+  assertEquals(data.diff.names, [...all.names, name]);
+
+  await sleep(100);
+  const after = await (
+    await fetch("http://localhost:4000/cache-changes")
+  ).json();
+  assert(after.count > before.count);
+});
+
+Deno.test("Incremental cache updates", async () => {
+  const before = await (
+    await fetch("http://localhost:4000/cache-version")
+  ).json();
+  const resp = await fetch("http://localhost:4000/update-cache");
+  const { failed, cache } = await resp.json();
+  assert(failed);
+  assertEquals(cache.ckey, 3);
+  assertEquals(cache.dkey.nested[0], 4);
+
+  const after = await (
+    await fetch("http://localhost:4000/cache-version")
+  ).json();
+  assertEquals(after.version, before.version + 2);
+
+  const nested = await (
+    await fetch("http://localhost:4000/update-in-with-cache")
+  ).json();
+  assertEquals(nested, { ckey: 3, ekey: 3 });
+});
+
+Deno.test("Request ids", async () => {
+  const given = await fetch("http://localhost:4000/cache-version", {
+    headers: { "x-request-id": "test-request-1" },
+  });
+  await given.body?.cancel();
+  assertEquals(given.headers.get("x-request-id"), "test-request-1");
+
+  const generated = await fetch("http://localhost:4000/cache-version");
+  await generated.body?.cancel();
+  assertEquals(generated.headers.get("x-request-id").length, 36);
+});
+
+Deno.test("Prometheus metrics", async () => {
+  for (const lang of ["de", "de", "en"]) {
+    const resp = await fetch(`http://localhost:4000/greet/${lang}`);
+    await resp.text();
+  }
+  await (await fetch("http://localhost:4000/db-json")).text();
+
+  const resp = await fetch("http://localhost:4000/metrics");
+  assert(resp.headers.get("content-type").startsWith("text/plain"));
+  const text = await resp.text();
+  const sample = (prefix) => {
+    const line = text.split("\n").find((line) => line.startsWith(prefix));
+    return line && Number(line.slice(prefix.length).trim());
+  };
+  assert(sample('test_greetings_total{lang="de"}') >= 2);
+  assert(sample('test_greetings_total{lang="en"}') >= 1);
+  assert(sample("test_greeting_length_count") >= 3);
+  assert(
+    sample(
+      'http_requests_total{method="GET",route="/greet/:lang",status="200"}'
+    ) >= 3
+  );
+  assert(text.includes("http_request_duration_seconds_bucket"));
+  assert(text.includes('js_worker_queue_depth{worker="js-worker-0"}'));
+  assert(sample('v8_heap_used_bytes{worker="js-worker-0"}') > 0);
+  assert(text.includes('db_query_duration_seconds_count{op="query"}'));
+});
+
+// Spans are written once they end, which can be after the response.
+async function traceSpans(traceId, names) {
+  for (let i = 0; i < 100; i++) {
+    const lines = await Deno.readTextFile("traces.jsonl").catch(() => "");
+    const spans = lines
+      .split("\n")
+      .filter((line) => line)
+      .map((line) => JSON.parse(line))
+      .filter((span) => span.traceId === traceId);
+    if (names.every((name) => spans.some((span) => span.name === name))) {
+      return spans;
+    }
+    await sleep(20);
+  }
+  throw new Error(`trace ${traceId} is missing spans`);
+}
+
+Deno.test("Trace spans and traceparent propagation", async () => {
+  // answers with the traceparent the app's fetch sent
+  const upstream = Deno.serve(
+    { hostname: "127.0.0.1", port: 4002, onListen() {} },
+    (req) => new Response(req.headers.get("traceparent") ?? "")
+  );
+  try {
+    const traceIds = [
+      "4bf92f3577b34da6a3ce929d0e0e4736",
+      "5c0a3f4688c45eb7b4df03ae1f1f5847",
+    ];
+    // both handlers run on the worker's event loop at the same time
+    const results = await Promise.all(
+      traceIds.map(async (traceId) => {
+        const resp = await fetch("http://localhost:4000/traced", {
+          headers: { traceparent: `00-${traceId}-00f067aa0ba902b7-01` },
+        });
+        assertEquals(resp.status, 200);
+        return await resp.json();
+      })
+    );
+
+    for (const [i, traceId] of traceIds.entries()) {
+      const spans = await traceSpans(traceId, [
+        "request",
+        "dispatch",
+        "handler",
+        "db.query",
+        "fetch",
+      ]);
+      const named = (name) => spans.filter((span) => span.name === name);
+
+      const [request] = named("request");
+      assertEquals(request.parentSpanId, "00f067aa0ba902b7");
+      assertEquals(request.kind, "Server");
+      const [dispatch] = named("dispatch");
+      assertEquals(dispatch.parentSpanId, request.spanId);
+      const [handler] = named("handler");
+      assertEquals(handler.parentSpanId, dispatch.spanId);
+      assertEquals(handler.attributes.route, "/traced");
+
+      // each request's query and fetch belong to it, not to the other one
+      const [query] = named("db.query");
+      assertEquals(query.parentSpanId, handler.spanId);
+      const [fetchSpan] = named("fetch");
+      assertEquals(fetchSpan.parentSpanId, handler.spanId);
+      assertEquals(fetchSpan.attributes.status, "200");
+      assertEquals(results[i].traceparent, `00-${traceId}-${fetchSpan.spanId}-01`);
+    }
+  } finally {
+    await upstream.shutdown();
+  }
+});
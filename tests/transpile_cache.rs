@@ -0,0 +1,41 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum_script::ScriptApp;
+use std::fs;
+use std::path::Path;
+
+async fn double(cache_dir: &Path) -> (StatusCode, String) {
+    let app = ScriptApp::builder()
+        .entry("tests/fixtures/transpile_cache.js")
+        .transpile_cache(cache_dir)
+        .build()
+        .await
+        .unwrap();
+    return common::get(&app, "/double").await;
+}
+
+#[tokio::test]
+async fn ignores_tampered_cache_entries() {
+    let dir = Path::new("target/transpile-cache-test");
+    let _ = fs::remove_dir_all(dir);
+    assert_eq!(double(dir).await, (StatusCode::OK, String::from("42")));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+    let entries = fs::read_dir(dir)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    let path = entries[0].path();
+    let tampered = fs::read_to_string(&path).unwrap().replace("n * 2", "n * 3");
+    fs::write(&path, tampered).unwrap();
+
+    // the entry no longer matches its hash, so the module is transpiled again
+    assert_eq!(double(dir).await, (StatusCode::OK, String::from("42")));
+}
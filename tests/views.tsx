@@ -0,0 +1,66 @@
+/** @jsx h */
+/** @jsxFrag Fragment */
+
+class Html {
+  constructor(readonly value: string) {}
+
+  toString(): string {
+    return this.value;
+  }
+}
+
+type Child = Html | string | number | null | undefined | Child[];
+type Component<P> = (props: P & { children: Child[] }) => Html;
+
+function escape(text: string): string {
+  return text
+    .replaceAll("&", "&amp;")
+    .replaceAll("<", "&lt;")
+    .replaceAll(">", "&gt;")
+    .replaceAll('"', "&quot;");
+}
+
+function render(child: Child): string {
+  if (child instanceof Html) return child.value;
+  if (Array.isArray(child)) return child.map(render).join("");
+  if (child === null || child === undefined) return "";
+  return escape(String(child));
+}
+
+export function h<P extends Record<string, unknown>>(
+  tag: string | Component<P>,
+  props: P | null,
+  ...children: Child[]
+): Html {
+  if (typeof tag === "function") {
+    return tag({ ...(props ?? ({} as P)), children });
+  }
+  const attrs = Object.entries(props ?? {})
+    .map(([name, value]) => ` ${name}="${escape(String(value))}"`)
+    .join("");
+  return new Html(`<${tag}${attrs}>${render(children)}</${tag}>`);
+}
+
+export function Fragment({ children }: { children: Child[] }): Html {
+  return new Html(render(children));
+}
+
+interface GreetingProps {
+  name: string;
+  excited?: boolean;
+}
+
+export function Greeting({ name, excited = false }: GreetingProps): Html {
+  return (
+    <>
+      <p class="greeting">
+        Hello, {name}
+        {excited ? "!" : "."}
+      </p>
+    </>
+  );
+}
+
+export function failTyped(reason: string): never {
+  throw new Error(`typed failure: ${reason}`);
+}